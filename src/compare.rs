@@ -0,0 +1,164 @@
+//! `--compare-embedded-subs`: extract a video's own subtitle track and compare
+//! whisper's transcript against it word by word.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use similar::{Algorithm, DiffOp, capture_diff_slices};
+
+use crate::{Subtitle, cs_to_srt_time, ffmpeg_path, parse_srt};
+
+/// Extract the first subtitle stream of `input` to `dest` as SRT and read it
+/// back. Image-based tracks such as DVD or Blu-ray subtitles can't be
+/// converted to text and fail like a missing track.
+pub(crate) fn extract_embedded_subtitles(
+    input: &Path,
+    dest: &Path,
+) -> Result<Vec<Subtitle>, String> {
+    let output = Command::new(ffmpeg_path())
+        .args(["-nostdin", "-y", "-loglevel", "error", "-i"])
+        .arg(input)
+        .args(["-map", "0:s:0", "-c:s", "srt"])
+        .arg(dest)
+        .output()
+        .map_err(|e| format!("cannot run ffmpeg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "no text subtitle stream could be extracted: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let content = fs::read_to_string(dest).map_err(|e| e.to_string())?;
+    parse_srt(&content).map_err(|e| e.to_string())
+}
+
+/// The words of `subs`, lowercased and without punctuation or formatting tags
+/// so only real wording differences count, each with its cue's start time.
+fn comparable_words(subs: &[Subtitle]) -> (Vec<String>, Vec<u64>) {
+    let mut words = Vec::new();
+    let mut times = Vec::new();
+    for sub in subs {
+        for word in strip_tags(&sub.text).split_whitespace() {
+            let word = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+            if !word.is_empty() {
+                words.push(word);
+                times.push(sub.start_time_cs);
+            }
+        }
+    }
+    (words, times)
+}
+
+/// Remove `<i>`-style tags and `{\an8}`-style overrides that subtitle tracks
+/// often carry.
+fn strip_tags(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut closing = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => plain.push(c),
+            (Some(end), c) if c == end => {
+                closing = None;
+                plain.push(' ');
+            }
+            (Some(_), _) => {}
+        }
+    }
+    plain
+}
+
+/// A `<stem>_diff.txt` report of where `transcript` diverges from the
+/// `reference` subtitles, and the word error rate against them.
+pub(crate) fn compare_transcripts(
+    reference: &[Subtitle],
+    transcript: &[Subtitle],
+) -> (String, f64) {
+    let (expected, expected_times) = comparable_words(reference);
+    let (actual, actual_times) = comparable_words(transcript);
+    let mut errors = 0;
+    let mut hunks = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, &expected, &actual) {
+        let (old, new) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => (old_index..old_index + old_len, 0..0),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => (0..0, new_index..new_index + new_len),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => (old_index..old_index + old_len, new_index..new_index + new_len),
+        };
+        // A substitution counts once per word; any surplus is an insertion or deletion
+        errors += old.len().max(new.len());
+        let time_cs = expected_times
+            .get(old.start)
+            .filter(|_| !old.is_empty())
+            .or(actual_times.get(new.start))
+            .copied()
+            .unwrap_or(0);
+        hunks.push(format!(
+            "[{}]\n- embedded: {}\n+ whisper:  {}\n",
+            cs_to_srt_time(time_cs),
+            expected[old].join(" "),
+            actual[new].join(" ")
+        ));
+    }
+
+    let word_error_rate = errors as f64 / expected.len().max(1) as f64;
+    let mut report = format!(
+        "Embedded subtitles: {} words; whisper: {} words\nWord error rate: {:.1}% ({} errors)\n",
+        expected.len(),
+        actual.len(),
+        word_error_rate * 100.0,
+        errors
+    );
+    for hunk in hunks {
+        report.push('\n');
+        report.push_str(&hunk);
+    }
+    (report, word_error_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start_time_cs: u64, text: &str) -> Subtitle {
+        Subtitle {
+            seq: 1,
+            start_time_cs,
+            end_time_cs: start_time_cs + 100,
+            text: text.to_string(),
+            words: Vec::new(),
+            lang: None,
+            confidence: None,
+            speaker: None,
+        }
+    }
+
+    #[test]
+    fn report_lists_diverging_words_with_their_time() {
+        let reference = [
+            cue(0, "<i>- The quick brown fox</i>"),
+            cue(6_000, "{\\an8}jumps over the dog."),
+        ];
+        let transcript = [cue(0, " The quack brown fox"), cue(6_010, " jumps over the lazy dog")];
+        let (report, word_error_rate) = compare_transcripts(&reference, &transcript);
+        assert_eq!(word_error_rate, 2.0 / 8.0);
+        assert_eq!(
+            report,
+            "Embedded subtitles: 8 words; whisper: 9 words\n\
+             Word error rate: 25.0% (2 errors)\n\
+             \n[00:00:00,000]\n- embedded: quick\n+ whisper:  quack\n\
+             \n[00:01:00,100]\n- embedded: \n+ whisper:  lazy\n"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! HTTP downloads with retries: the ffmpeg builds and ggml models.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::blocking::Response;
+use sha2::{Digest, Sha256};
+
+const MODEL_BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+const TDRZ_MODEL_BASE_URL: &str =
+    "https://huggingface.co/akashmjn/tinydiarize-whisper.cpp/resolve/main";
+
+/// Where and how to fetch models, from `--model-url`, `--model-prefix`,
+/// `--download-retries` and `--hf-token`.
+pub(crate) struct DownloadConfig<'a> {
+    pub(crate) base_url: Option<&'a str>,
+    pub(crate) prefix: &'a str,
+    pub(crate) retries: u32,
+    pub(crate) token: Option<&'a str>,
+}
+
+/// Download URL of a known model; quantized variants share the `<prefix><name>.bin`
+/// layout. Without a custom base URL the Hugging Face mirror is used.
+fn model_url(name: &str, config: &DownloadConfig) -> String {
+    let base = match config.base_url {
+        Some(base) => base.trim_end_matches('/'),
+        // Tinydiarize models are published in a separate repository
+        None if name.contains("tdrz") => TDRZ_MODEL_BASE_URL,
+        None => MODEL_BASE_URL,
+    };
+    format!("{}/{}{}.bin", base, config.prefix, name)
+}
+
+/// Why a download attempt failed, and whether trying again could help.
+enum AttemptError {
+    Retry(Box<dyn Error>),
+    Fatal(Box<dyn Error>),
+}
+
+/// Run `attempt` until it succeeds, retrying up to `retries` times with
+/// exponential backoff for errors that may be transient.
+fn with_retries<T>(
+    retries: u32,
+    mut attempt: impl FnMut() -> Result<T, AttemptError>,
+) -> Result<T, Box<dyn Error>> {
+    let mut failures = 0;
+    loop {
+        let err = match attempt() {
+            Ok(value) => return Ok(value),
+            Err(AttemptError::Fatal(e)) => return Err(e),
+            Err(AttemptError::Retry(e)) => e,
+        };
+
+        if failures >= retries {
+            return Err(err);
+        }
+        let delay = Duration::from_secs(1 << failures.min(6));
+        eprintln!(
+            "Download attempt {} failed ({}), retrying in {}s...",
+            failures + 1,
+            err,
+            delay.as_secs()
+        );
+        std::thread::sleep(delay);
+        failures += 1;
+    }
+}
+
+/// Start a GET request, sending `token` as a bearer token if given. Proxies are
+/// taken from `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` by reqwest itself.
+/// Network failures and 5xx responses are worth retrying; client errors such
+/// as 404 are not.
+fn get(url: &str, token: Option<&str>) -> Result<Response, AttemptError> {
+    let mut request = reqwest::blocking::Client::new().get(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    match request.send() {
+        Ok(response) if response.status().is_success() => Ok(response),
+        Ok(response) if response.status().is_server_error() => Err(AttemptError::Retry(
+            format!("server responded with {}", response.status()).into(),
+        )),
+        Ok(response) if matches!(response.status().as_u16(), 401 | 403) => {
+            let hint = if token.is_some() {
+                "check that --hf-token has access to it"
+            } else {
+                "it may need a token, see --hf-token"
+            };
+            Err(AttemptError::Fatal(
+                format!("Download of {} failed: {} ({})", url, response.status(), hint).into(),
+            ))
+        }
+        Ok(response) => Err(AttemptError::Fatal(
+            format!("Download of {} failed: {}", url, response.status()).into(),
+        )),
+        Err(e) => Err(AttemptError::Retry(e.into())),
+    }
+}
+
+/// Fetch `url` into memory, retrying network failures and 5xx responses with
+/// exponential backoff. Client errors such as 404 fail immediately.
+pub(crate) fn download_with_retries(url: &str, retries: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    with_retries(retries, || {
+        let response = get(url, None)?;
+        let bytes = response
+            .bytes()
+            .map_err(|e| AttemptError::Retry(e.into()))?;
+        Ok(bytes.to_vec())
+    })
+}
+
+/// Lowercase hex SHA-256 of `bytes`.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Fail unless `bytes` hash to the hex SHA-256 `expected`, in either case.
+pub(crate) fn verify_sha256(bytes: &[u8], expected: &str) -> Result<(), Box<dyn Error>> {
+    let actual = sha256_hex(bytes);
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(format!("SHA-256 mismatch: expected {}, got {}", expected.trim(), actual).into())
+    }
+}
+
+/// Download the known model `name` to `dest`. The file is streamed to
+/// `<dest>.part` and only renamed once complete, so an interrupted download
+/// never leaves a truncated model behind. `token` is sent as a Hugging Face
+/// access token for gated or private repositories.
+pub(crate) fn download_ggml_model(
+    name: &str,
+    dest: &Path,
+    config: &DownloadConfig,
+) -> Result<(), Box<dyn Error>> {
+    let url = model_url(name, config);
+    let (retries, token) = (config.retries, config.token);
+    let mut part_path = dest.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = Path::new(&part_path);
+
+    status!("Downloading {} from {}", name, url);
+    with_retries(retries, || {
+        let response = get(&url, token)?;
+        let pb = match response.content_length() {
+            Some(len) => indicatif::ProgressBar::new(len),
+            None => indicatif::ProgressBar::no_length(),
+        };
+        pb.set_style(
+            indicatif::ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+
+        let mut file = fs::File::create(part_path).map_err(|e| AttemptError::Fatal(e.into()))?;
+        let copied = io::copy(&mut pb.wrap_read(response), &mut file);
+        pb.finish_and_clear();
+        copied.map_err(|e| AttemptError::Retry(e.into()))?;
+        Ok(())
+    })?;
+
+    if is_zip(part_path)? {
+        // Some mirrors ship the model zipped; the official files are plain .bin
+        extract_model(part_path, dest)?;
+        fs::remove_file(part_path)?;
+    } else {
+        fs::rename(part_path, dest)?;
+    }
+    status!("Model saved to {}", dest.display());
+    Ok(())
+}
+
+/// Whether `path` starts with the `PK\x03\x04` local file header of a ZIP archive.
+fn is_zip(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut file = fs::File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == b"PK\x03\x04"),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write the single `.bin` entry of the archive at `archive_path` to `dest`.
+fn extract_model(archive_path: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
+    let bin_names: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.ends_with(".bin"))
+        .map(str::to_string)
+        .collect();
+    let [bin_name] = bin_names.as_slice() else {
+        return Err(format!(
+            "expected one .bin model in the archive, found {}",
+            bin_names.len()
+        )
+        .into());
+    };
+
+    let mut entry = archive.by_name(bin_name)?;
+    let mut out = fs::File::create(dest)?;
+    io::copy(&mut entry, &mut out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zipped_models_are_extracted_and_plain_ones_recognised() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.bin");
+        fs::write(&plain, b"lmgg plain model").unwrap();
+        assert!(!is_zip(&plain).unwrap());
+
+        let archive = dir.path().join("model.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+        writer
+            .start_file("ggml-tiny.bin", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        io::Write::write_all(&mut writer, b"lmgg zipped model").unwrap();
+        writer.finish().unwrap();
+        assert!(is_zip(&archive).unwrap());
+
+        let dest = dir.path().join("ggml-tiny.bin");
+        extract_model(&archive, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"lmgg zipped model");
+    }
+
+    fn config<'a>(base_url: Option<&'a str>, prefix: &'a str) -> DownloadConfig<'a> {
+        DownloadConfig { base_url, prefix, retries: 0, token: None }
+    }
+
+    #[test]
+    fn sha256_checks_ignore_case_and_surrounding_space() {
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_hex(b"abc"), abc);
+        assert!(verify_sha256(b"abc", &format!(" {}\n", abc.to_uppercase())).is_ok());
+        let err = verify_sha256(b"abd", abc).unwrap_err().to_string();
+        assert!(err.contains("mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn model_urls_match_published_layout() {
+        let default = config(None, "ggml-");
+        assert_eq!(
+            model_url("base", &default),
+            "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-base.bin"
+        );
+        assert_eq!(
+            model_url("tiny.en-q5_1", &default),
+            "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny.en-q5_1.bin"
+        );
+        assert_eq!(
+            model_url("small.en-tdrz", &default),
+            "https://huggingface.co/akashmjn/tinydiarize-whisper.cpp/resolve/main/ggml-small.en-tdrz.bin"
+        );
+    }
+
+    #[test]
+    fn custom_model_url_and_prefix_replace_the_defaults() {
+        let mirror = config(Some("https://mirror.example/models/"), "whisper-");
+        assert_eq!(
+            model_url("base.en", &mirror),
+            "https://mirror.example/models/whisper-base.en.bin"
+        );
+        assert_eq!(
+            model_url("small.en-tdrz", &mirror),
+            "https://mirror.example/models/whisper-small.en-tdrz.bin"
+        );
+    }
+}
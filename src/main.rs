@@ -1,95 +1,183 @@
 use hound::{SampleFormat, WavReader};
+use std::collections::HashSet;
 use std::error::Error;
+use std::ffi::{c_int, c_void};
 use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::Command;
-use std::time::Duration;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use whisper_rs::{
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperError,
+    WhisperState, whisper_rs_sys,
+};
 use clap::Parser;
+use serde::Serialize;
+
+/// Set with `--stream-jsonl`, whose JSON lines must be all there is on stdout.
+static STDOUT_IS_JSONL: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for status messages, which go to stderr while `--stream-jsonl`
+/// owns stdout.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::STDOUT_IS_JSONL.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod compare;
+mod download;
+#[cfg(feature = "mic")]
+mod mic;
+mod probe;
+mod server;
 
-// If windows: use ./ffmpeg else use ffmpeg
+// If windows: use ffmpeg.exe else use ffmpeg
 const FFMPEG_PATH: &str = if cfg!(windows) {
-    "./ffmpeg.exe"
+    "ffmpeg.exe"
 } else {
     "ffmpeg"
 };
+/// Where `download_ffmpeg` puts the build it downloads.
+const LOCAL_FFMPEG_PATH: &str = if cfg!(windows) {
+    "./ffmpeg.exe"
+} else {
+    "./ffmpeg"
+};
+
+/// Whether an ffmpeg on the PATH runs, checked once per process.
+fn ffmpeg_on_path() -> bool {
+    static ON_PATH: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *ON_PATH.get_or_init(|| Command::new(FFMPEG_PATH).arg("-version").output().is_ok())
+}
+
+/// The ffmpeg to run: the one on the PATH if there is one, otherwise a build
+/// that `download_ffmpeg` put in the working directory.
+fn ffmpeg_path() -> &'static str {
+    if !ffmpeg_on_path() && Path::new(LOCAL_FFMPEG_PATH).is_file() {
+        LOCAL_FFMPEG_PATH
+    } else {
+        FFMPEG_PATH
+    }
+}
+
+/// The ffprobe next to the ffmpeg that `ffmpeg_path` picks, which is on the
+/// PATH when that ffmpeg is.
+fn ffprobe_path() -> PathBuf {
+    let name = if cfg!(windows) { "ffprobe.exe" } else { "ffprobe" };
+    Path::new(ffmpeg_path()).with_file_name(name)
+}
+/// The only rate whisper accepts. ffmpeg resamples to it, WAV input is checked
+/// against it and timestamps are derived from it, so they can't disagree.
+const SAMPLE_RATE: usize = whisper_rs_sys::WHISPER_SAMPLE_RATE as usize;
+const CHUNK_SIZE: usize = 30 * SAMPLE_RATE; // 30 seconds
+const MIN_SAMPLES: usize = SAMPLE_RATE; // 1 second; shorter clips rarely transcribe
+/// How much of the previous input's transcript `--prompt-from-previous` carries over.
+const PROMPT_TAIL_CHARS: usize = 200;
+
+/// Set by the Ctrl-C handler; the chunk loop stops and flushes what it has.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[allow(dead_code)]
 const YT_DLP_PATH: &str = if cfg!(windows) {
     "./yt-dlp.exe"
 } else {
     "yt-dlp"
 };
 
-fn parse_wav_file(path: &Path) -> io::Result<Vec<i16>> {
-    let reader = WavReader::open(path).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("Error opening WAV file: {}", e),
-        )
-    })?;
+/// Read the 16kHz mono WAV that ffmpeg converted an input to. Inputs never come
+/// here directly, so WAV flavours hound reads poorly, such as 24-bit samples in
+/// 32-bit `WAVE_FORMAT_EXTENSIBLE` containers, are decoded by ffmpeg instead.
+fn parse_wav_file(path: &Path) -> io::Result<Vec<f32>> {
+    let reader = WavReader::open(path)
+        .map_err(|e| io::Error::other(format!("Error opening WAV file: {}", e)))?;
+    let spec = reader.spec();
 
-    if reader.spec().channels != 1 {
+    if spec.channels != 1 {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
             "Expected mono audio file",
         ));
     }
-    if reader.spec().sample_format != SampleFormat::Int {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Expected integer sample format",
-        ));
-    }
-    if reader.spec().sample_rate != 16000 {
+    if spec.sample_rate as usize != SAMPLE_RATE {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
-            "Expected 16KHz sample rate",
-        ));
-    }
-    if reader.spec().bits_per_sample != 16 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Expected 16 bits per sample",
+            format!("Expected {} Hz sample rate, got {} Hz", SAMPLE_RATE, spec.sample_rate),
         ));
     }
 
-    Ok(reader
-        .into_samples::<i16>()
-        .map(|x| x.expect("sample"))
-        .collect())
+    // Read the native sample format and scale it to the [-1.0, 1.0] range whisper expects
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader.into_samples::<f32>().collect::<Result<Vec<_>, _>>(),
+        (SampleFormat::Int, bits @ (8 | 16 | 24 | 32)) => {
+            let scale = (1u64 << (bits - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()
+        }
+        (format, bits) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported sample format: {}-bit {:?}", bits, format),
+            ));
+        }
+    };
+
+    samples.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
-fn download_ffmpeg() -> Result<(), Box<dyn std::error::Error>> {
-    // Check if ffmpeg is already installed
-    if Command::new(FFMPEG_PATH).output().is_ok() {
-        println!(
+fn download_ffmpeg(
+    retries: u32,
+    allow_download: bool,
+    force: bool,
+    sha256: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if ffmpeg is already installed; a forced download only replaces a downloaded build
+    let installed = if force {
+        ffmpeg_on_path()
+    } else {
+        Command::new(ffmpeg_path()).output().is_ok()
+    };
+    if installed {
+        status!(
             "FFmpeg is already installed. Skipping download. If you want to reinstall, delete the FFmpeg binary and run this script again."
         );
         return Ok(());
     }
+    if !allow_download {
+        return Err("FFmpeg is not installed and --no-download-ffmpeg was given".into());
+    }
 
     if cfg!(target_os = "windows") {
         let url = "https://www.gyan.dev/ffmpeg/builds/ffmpeg-git-full.7z";
 
-        println!("Downloading FFmpeg for Windows...");
-        let response = reqwest::blocking::get(url)?;
-        if !response.status().is_success() {
-            return Err("Failed to download FFmpeg".into());
-        }
+        status!("Downloading FFmpeg for Windows...");
+        let bytes = download::download_with_retries(url, retries)?;
+        // gyan.dev publishes the digest of each build next to it
+        let published = download::download_with_retries(&format!("{}.sha256", url), retries)?;
+        let published = String::from_utf8_lossy(&published);
+        check_ffmpeg_download(&bytes, sha256.or(published.split_whitespace().next()))?;
 
         let temp_file = tempfile::NamedTempFile::new()?;
-        fs::write(temp_file.path(), &response.bytes()?)?;
+        fs::write(temp_file.path(), &bytes)?;
 
-        println!("Extracting FFmpeg...");
+        status!("Extracting FFmpeg...");
         sevenz_rust::decompress_file(temp_file.path(), Path::new("."))?;
 
         // Find the ffmpeg folder "ffmpeg*"
         let ffmpeg_folder = fs::read_dir(".")?
             .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_type().ok().map_or(false, |t| t.is_dir()))
-            .filter(|entry| entry.file_name().to_str().unwrap_or("").starts_with("ffmpeg"))
-            .next();
+            .filter(|entry| entry.file_type().ok().is_some_and(|t| t.is_dir()))
+            .find(|entry| entry.file_name().to_str().unwrap_or("").starts_with("ffmpeg"));
 
         let ffmpeg_folder = match ffmpeg_folder {
             Some(folder) => folder,
@@ -100,22 +188,110 @@ fn download_ffmpeg() -> Result<(), Box<dyn std::error::Error>> {
         let src = ffmpeg_folder.path().join("bin").join("ffmpeg.exe");
         let dst = Path::new("ffmpeg.exe");
 
-        println!("{} -> {}", src.to_str().unwrap(), dst.to_str().unwrap());
+        status!("{} -> {}", src.display(), dst.display());
 
         fs::rename(src, dst)?;
 
         // Remove the temporary zip file
         fs::remove_file(temp_file.path())?;
         fs::remove_dir_all(ffmpeg_folder.path())?;
+    } else if cfg!(target_os = "macos") {
+        // evermeet.cx only builds for Intel; osxexperts.net has native Apple silicon builds
+        let url = if cfg!(target_arch = "aarch64") {
+            "https://www.osxexperts.net/ffmpeg71arm.zip"
+        } else {
+            "https://evermeet.cx/ffmpeg/getrelease/zip"
+        };
+
+        status!("Downloading FFmpeg for macOS...");
+        let bytes = download::download_with_retries(url, retries)?;
+        check_ffmpeg_download(&bytes, sha256)?;
+        status!("Extracting FFmpeg...");
+        extract_ffmpeg_from_zip(&bytes, Path::new(LOCAL_FFMPEG_PATH))?;
+    } else if cfg!(target_os = "linux") {
+        let arch = if cfg!(target_arch = "aarch64") {
+            "arm64"
+        } else {
+            "amd64"
+        };
+        let url = format!(
+            "https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-{}-static.tar.xz",
+            arch
+        );
+
+        status!("Downloading FFmpeg for Linux...");
+        let bytes = download::download_with_retries(&url, retries)?;
+        check_ffmpeg_download(&bytes, sha256)?;
+        status!("Extracting FFmpeg...");
+        extract_ffmpeg_from_tar_xz(&bytes, Path::new(LOCAL_FFMPEG_PATH))?;
+    } else {
+        return Err("no FFmpeg build is available for this platform; please install it".into());
+    }
+
+    Ok(())
+}
+
+/// Check a downloaded FFmpeg archive against the hex SHA-256 `expected`. The
+/// macOS and Linux hosts publish no digest, so without `--ffmpeg-sha256` the
+/// archive's own digest is printed for checking by hand instead.
+fn check_ffmpeg_download(bytes: &[u8], expected: Option<&str>) -> Result<(), Box<dyn Error>> {
+    match expected {
+        Some(expected) => download::verify_sha256(bytes, expected),
+        None => {
+            eprintln!(
+                "Warning: the FFmpeg download is unverified; its SHA-256 is {} (check it with \
+                 --ffmpeg-sha256)",
+                download::sha256_hex(bytes)
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Write the `ffmpeg` binary from a zip archive to `dest` and make it executable.
+fn extract_ffmpeg_from_zip(archive: &[u8], dest: &Path) -> Result<(), Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(archive))?;
+    let name = archive
+        .file_names()
+        .find(|name| Path::new(name).file_name().is_some_and(|file| file == "ffmpeg"))
+        .map(str::to_string)
+        .ok_or("ffmpeg binary not found in the downloaded archive")?;
+    io::copy(&mut archive.by_name(&name)?, &mut fs::File::create(dest)?)?;
+    make_executable(dest)
+}
+
+/// Write the `ffmpeg` binary from a `.tar.xz` archive to `dest` and make it executable.
+fn extract_ffmpeg_from_tar_xz(archive: &[u8], dest: &Path) -> Result<(), Box<dyn Error>> {
+    let mut tar_bytes = Vec::new();
+    lzma_rs::xz_decompress(&mut io::Cursor::new(archive), &mut tar_bytes)
+        .map_err(|e| format!("Failed to decompress the FFmpeg archive: {:?}", e))?;
+    let mut tar = tar::Archive::new(tar_bytes.as_slice());
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.file_name().is_some_and(|file| file == "ffmpeg") {
+            io::copy(&mut entry, &mut fs::File::create(dest)?)?;
+            return make_executable(dest);
+        }
     }
+    Err("ffmpeg binary not found in the downloaded archive".into())
+}
 
+fn make_executable(path: &Path) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
     Ok(())
 }
 
+#[allow(dead_code)]
 fn download_yt_dlp() -> Result<(), Box<dyn Error>> {
     // Check if yt-dlp is already installed
     if Command::new(YT_DLP_PATH).output().is_ok() {
-        println!(
+        status!(
             "YT-DLP is already installed. Skipping download. If you want to reinstall, delete the FFmpeg binary and run this script again."
         );
         return Ok(());
@@ -124,287 +300,6374 @@ fn download_yt_dlp() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Smallest converted WAV worth parsing: ffmpeg's header plus a few samples.
+const MIN_WAV_BYTES: u64 = 100;
+const EMPTY_FFMPEG_OUTPUT: &str = "ffmpeg produced empty output - is the input a valid audio file?";
+/// Decoded audio above this many bytes of f32 samples (about 9 hours at 16kHz)
+/// needs `--allow-large`, since the whole input is held in memory.
+const MAX_DECODED_BYTES: u64 = 2 << 30;
+
+/// Refuse, or with `allow_large` only warn about, inputs whose decoded samples
+/// would exceed `MAX_DECODED_BYTES`.
+fn check_decoded_size(sample_count: u64, label: &str, allow_large: bool) -> Result<(), String> {
+    let bytes = sample_count * size_of::<f32>() as u64;
+    if bytes <= MAX_DECODED_BYTES {
+        return Ok(());
+    }
+    let description = format!(
+        "{} is {:.1} hours long and needs {:.1} GiB of memory once decoded",
+        label,
+        sample_count as f64 / SAMPLE_RATE as f64 / 3600.0,
+        bytes as f64 / (1u64 << 30) as f64
+    );
+    if allow_large {
+        eprintln!("Warning: {}", description);
+        Ok(())
+    } else {
+        Err(format!("{}; pass --allow-large to load it anyway", description))
+    }
+}
+
+/// Length of `input_path` in seconds as reported by ffprobe, so an input can be
+/// turned away before it is decoded. `None` when ffprobe isn't installed (the
+/// static ffmpeg builds that are downloaded don't include it) or can't tell.
+fn probe_duration(input_path: &Path) -> Option<f64> {
+    let output = Command::new(ffprobe_path())
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "csv=p=0"])
+        .arg(input_path)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Refuse an input longer than `--max-duration`.
+fn check_max_duration(secs: f64, label: &str, max_secs: Option<f64>) -> Result<(), String> {
+    match max_secs {
+        Some(max_secs) if secs > max_secs => Err(format!(
+            "{} is {:.1}s long, more than the {}s allowed by --max-duration",
+            label, secs, max_secs
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// How much audio to decode when `--max-duration` is set: a second past the
+/// limit is enough to tell that an input is too long without decoding all of it.
+fn max_duration_decode_secs(max_secs: Option<f64>) -> Option<f64> {
+    max_secs.map(|max_secs| max_secs + 1.0)
+}
+
+/// Refuse audio decoded with the `max_duration_decode_secs` cap. Decoding
+/// stopped past the limit, so the input's real length isn't known.
+fn check_decoded_duration(
+    sample_count: usize,
+    label: &str,
+    max_secs: Option<f64>,
+) -> Result<(), String> {
+    match max_secs {
+        Some(max_secs) if sample_count as f64 > max_secs * SAMPLE_RATE as f64 => Err(format!(
+            "{} is longer than the {}s allowed by --max-duration",
+            label, max_secs
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// ffmpeg `-af` filter chain selected by the preprocessing options, if any.
+fn audio_filter_chain(args: &Args) -> Option<String> {
+    let mut filters = Vec::new();
+    let highpass = args.input.highpass.or(args.input.clean_speech.then_some(80));
+    let lowpass = args.input.lowpass.or(args.input.clean_speech.then_some(8000));
+    if let Some(hz) = highpass {
+        filters.push(format!("highpass=f={}", hz));
+    }
+    if let Some(hz) = lowpass {
+        filters.push(format!("lowpass=f={}", hz));
+    }
+    if args.input.denoise {
+        filters.push("afftdn".to_string());
+    }
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+/// `audio_filter_chain`, preceded by a filter that keeps only `channel` when
+/// one channel of a multitrack input is transcribed on its own.
+fn input_filter_chain(args: &Args, channel: Option<usize>) -> Option<String> {
+    let isolate = channel.map(|channel| format!("pan=mono|c0=c{}", channel));
+    match (isolate, audio_filter_chain(args)) {
+        (Some(isolate), Some(chain)) => Some(format!("{},{}", isolate, chain)),
+        (isolate, chain) => isolate.or(chain),
+    }
+}
+
 fn ensure_wav_compatibility(
     input_path: &Path,
     output_path: &Path,
+    filter: Option<&str>,
+    max_secs: Option<f64>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    Command::new(FFMPEG_PATH)
-        .arg("-i")
-        .arg(input_path)
+    let mut command = Command::new(ffmpeg_path());
+    command.arg("-i").arg(input_path);
+    if let Some(filter) = filter {
+        command.arg("-af").arg(filter);
+    }
+    if let Some(max_secs) = max_secs {
+        command.arg("-t").arg(max_secs.to_string());
+    }
+    command
         .arg("-acodec")
         .arg("pcm_s16le")
         .arg("-ar")
-        .arg("16000")
+        .arg(SAMPLE_RATE.to_string())
         .arg("-ac")
         .arg("1")
         .arg(output_path)
         .spawn()?
         .wait()?;
 
+    // A corrupt input can leave ffmpeg with nothing but a WAV header to write
+    let size = fs::metadata(output_path).map(|m| m.len()).unwrap_or(0);
+    if size < MIN_WAV_BYTES {
+        return Err(EMPTY_FFMPEG_OUTPUT.into());
+    }
     Ok(())
 }
 
+/// Decode `input_path` with ffmpeg straight to 16kHz mono PCM on a pipe, so no
+/// intermediate WAV is written to disk.
+/// Decoding stops with an error once more than `max_samples` samples arrive,
+/// and quietly after `max_secs` of audio.
+fn decode_with_ffmpeg(
+    input_path: &Path,
+    filter: Option<&str>,
+    max_samples: Option<usize>,
+    max_secs: Option<f64>,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut command = Command::new(ffmpeg_path());
+    command.arg("-i").arg(input_path);
+    if let Some(filter) = filter {
+        command.arg("-af").arg(filter);
+    }
+    if let Some(max_secs) = max_secs {
+        command.arg("-t").arg(max_secs.to_string());
+    }
+    let mut child = command
+        .arg("-f")
+        .arg("s16le")
+        .arg("-acodec")
+        .arg("pcm_s16le")
+        .arg("-ar")
+        .arg(SAMPLE_RATE.to_string())
+        .arg("-ac")
+        .arg("1")
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().ok_or("ffmpeg stdout was not captured")?;
+    let samples = match read_pcm_s16le(stdout, max_samples) {
+        Ok(samples) => samples,
+        Err(e) => {
+            // Nobody reads the pipe any more, so ffmpeg would block on it forever
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e.into());
+        }
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("ffmpeg exited with {}", status).into());
+    }
+    if samples.is_empty() {
+        return Err(EMPTY_FFMPEG_OUTPUT.into());
+    }
+    Ok(samples)
+}
+
+/// Read little-endian 16-bit PCM and scale it to the [-1.0, 1.0] range whisper expects.
+/// Fails as soon as the input holds more than `max_samples` samples.
+fn read_pcm_s16le(mut reader: impl Read, max_samples: Option<usize>) -> io::Result<Vec<f32>> {
+    let mut samples = Vec::new();
+    let mut buf = [0u8; 64 * 1024];
+    // A read can end halfway through a sample; keep its low byte for the next one
+    let mut pending = None;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut bytes = &buf[..n];
+        if let Some(low) = pending.take() {
+            samples.push(i16::from_le_bytes([low, bytes[0]]) as f32 / 32768.0);
+            bytes = &bytes[1..];
+        }
+        let pairs = bytes.chunks_exact(2);
+        if let [low] = pairs.remainder() {
+            pending = Some(*low);
+        }
+        samples.extend(pairs.map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / 32768.0));
+        if max_samples.is_some_and(|max| samples.len() > max) {
+            return Err(io::Error::other(
+                "decoded audio is too large to hold in memory; pass --allow-large to load it",
+            ));
+        }
+    }
+    Ok(samples)
+}
+
 fn create_temporary_directory() -> Result<TempDir, Box<dyn Error>> {
     TempDir::new().map_err(|e| e.into())
 }
 
+/// A whisper.cpp ggml model and its approximate download size.
+struct ModelInfo {
+    name: &'static str,
+    size_mb: u32,
+    multilingual: bool,
+}
+
+const fn model(name: &'static str, size_mb: u32, multilingual: bool) -> ModelInfo {
+    ModelInfo {
+        name,
+        size_mb,
+        multilingual,
+    }
+}
+
+fn known_models() -> &'static [ModelInfo] {
+    const MODELS: &[ModelInfo] = &[
+        model("tiny", 75, true),
+        model("tiny.en", 75, false),
+        model("base", 142, true),
+        model("base.en", 142, false),
+        model("small", 466, true),
+        model("small.en", 466, false),
+        model("small.en-tdrz", 465, false),
+        model("medium", 1500, true),
+        model("medium.en", 1500, false),
+        model("large-v1", 2900, true),
+        model("large-v2", 2900, true),
+        model("large-v3", 2900, true),
+        model("large-v3-turbo", 1500, true),
+        // Quantized variants: smaller and faster at a small accuracy cost
+        model("tiny-q5_1", 31, true),
+        model("tiny.en-q5_1", 31, false),
+        model("tiny-q8_0", 42, true),
+        model("base-q5_1", 57, true),
+        model("base.en-q5_1", 57, false),
+        model("base-q8_0", 78, true),
+        model("small-q5_1", 181, true),
+        model("small.en-q5_1", 181, false),
+        model("small-q8_0", 252, true),
+        model("medium-q5_0", 514, true),
+        model("medium.en-q5_0", 514, false),
+        model("medium-q8_0", 785, true),
+        model("large-v2-q5_0", 1100, true),
+        model("large-v2-q8_0", 1500, true),
+        model("large-v3-q5_0", 1100, true),
+        model("large-v3-turbo-q5_0", 547, true),
+        model("large-v3-turbo-q8_0", 834, true),
+    ];
+    MODELS
+}
+
+/// Short names for known models, for `--model` and `download`.
+const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("turbo", "large-v3-turbo"),
+    ("turbo-q5", "large-v3-turbo-q5_0"),
+    ("turbo-q8", "large-v3-turbo-q8_0"),
+    ("large", "large-v3"),
+    ("large-q5", "large-v3-q5_0"),
+    ("large-v3-q5", "large-v3-q5_0"),
+    ("large-v3-turbo-q5", "large-v3-turbo-q5_0"),
+    ("large-v3-turbo-q8", "large-v3-turbo-q8_0"),
+    ("tiny-en", "tiny.en"),
+    ("base-en", "base.en"),
+    ("small-en", "small.en"),
+    ("medium-en", "medium.en"),
+    ("tdrz", "small.en-tdrz"),
+];
+
+/// The known model `name` refers to: its exact name, matched ignoring case,
+/// or an alias from `MODEL_ALIASES`.
+fn canonical_model_name(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_lowercase();
+    known_models()
+        .iter()
+        .map(|model| model.name)
+        .find(|known| *known == name)
+        .or_else(|| {
+            MODEL_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, target)| *target)
+        })
+}
+
+/// Tell the user which model an alias stands for, so they can spell it out
+/// next time. Exact names and paths print nothing.
+fn announce_model_alias(name: &str) {
+    if !Path::new(name).exists()
+        && let Some(canonical) = canonical_model_name(name)
+        && canonical != name
+    {
+        eprintln!("Model '{}' resolves to {}", name, canonical);
+    }
+}
+
+/// Recover the model name from a `ggml-<name>.bin` path, if it is a known model.
+fn known_model_name(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    let name = file_name.strip_prefix("ggml-")?.strip_suffix(".bin")?;
+    known_models()
+        .iter()
+        .find(|model| model.name == name)
+        .map(|model| model.name)
+}
+
+fn format_size_mb(size_mb: u32) -> String {
+    if size_mb >= 1000 {
+        format!("{:.1} GB", size_mb as f32 / 1000.0)
+    } else {
+        format!("{} MB", size_mb)
+    }
+}
+
+fn print_known_models() {
+    println!("{:<20} {:>9}  LANGUAGES", "MODEL", "SIZE");
+    for model in known_models() {
+        println!(
+            "{:<20} {:>9}  {}",
+            model.name,
+            format_size_mb(model.size_mb),
+            if model.multilingual {
+                "multilingual"
+            } else {
+                "English-only"
+            }
+        );
+    }
+    println!();
+    println!("ALIASES");
+    for (alias, target) in MODEL_ALIASES {
+        println!("{:<20} {}", alias, target);
+    }
+}
+
+/// Readable name of a ggml weight type as stored in a whisper model header.
+fn ftype_name(ftype: c_int) -> &'static str {
+    match ftype {
+        0 => "f32",
+        1 => "f16",
+        2 => "q4_0",
+        3 => "q4_1",
+        7 => "q8_0",
+        8 => "q5_0",
+        9 => "q5_1",
+        10 => "q2_k",
+        11 => "q3_k",
+        12 => "q4_k",
+        13 => "q5_k",
+        14 => "q6_k",
+        _ => "unknown",
+    }
+}
+
+/// Rough parameter count from the model dimensions: per layer, the encoder has
+/// self-attention and an MLP and the decoder adds cross-attention, on top of the
+/// token embedding, positional embeddings and the two input convolutions.
+fn estimate_parameters(ctx: &WhisperContext) -> u64 {
+    let dim = |value: c_int| value.max(0) as u64;
+    let audio_state = dim(ctx.model_n_audio_state());
+    let text_state = dim(ctx.model_n_text_state());
+    let conv = 3 * audio_state * (dim(ctx.model_n_mels()) + audio_state);
+    let encoder = dim(ctx.model_n_audio_layer()) * 12 * audio_state * audio_state;
+    let decoder = dim(ctx.model_n_text_layer()) * 16 * text_state * text_state;
+    let embeddings = dim(ctx.model_n_vocab()) * text_state
+        + dim(ctx.model_n_text_ctx()) * text_state
+        + dim(ctx.model_n_audio_ctx()) * audio_state;
+    conv + encoder + decoder + embeddings
+}
+
+fn print_model_info(ctx: &WhisperContext, whisper_path: &Path) {
+    println!("Model:        {}", whisper_path.display());
+    if let Ok(metadata) = fs::metadata(whisper_path) {
+        println!(
+            "File size:    {}",
+            format_size_mb((metadata.len() / 1_000_000) as u32)
+        );
+    }
+    println!(
+        "Type:         {}",
+        ctx.model_type_readable().unwrap_or_else(|_| "unknown".to_string())
+    );
+    println!(
+        "Languages:    {}",
+        if ctx.is_multilingual() {
+            "multilingual"
+        } else {
+            "English-only"
+        }
+    );
+    let ftype = ctx.model_ftype();
+    println!(
+        "Weights:      {}{}",
+        ftype_name(ftype),
+        if ftype >= 2 { " (quantized)" } else { "" }
+    );
+    println!(
+        "Parameters:   ~{}M",
+        (estimate_parameters(ctx) as f64 / 1e6).round()
+    );
+    println!("Vocabulary:   {} tokens", ctx.model_n_vocab());
+    println!(
+        "Audio:        {} layers, {} wide, {} mel bands, {} frames of context",
+        ctx.model_n_audio_layer(),
+        ctx.model_n_audio_state(),
+        ctx.model_n_mels(),
+        ctx.model_n_audio_ctx()
+    );
+    println!(
+        "Text:         {} layers, {} wide, {} tokens of context",
+        ctx.model_n_text_layer(),
+        ctx.model_n_text_state(),
+        ctx.model_n_text_ctx()
+    );
+}
+
+#[derive(Clone)]
 struct Subtitle {
     seq: u32,
     start_time_cs: u64, // centiseconds
     end_time_cs: u64,   // centiseconds
     text: String,
+    /// Per-word times from whisper's token timestamps; empty unless `--karaoke`
+    /// asked for them, and dropped by steps that re-split the text by interpolation
+    words: Vec<Word>,
+    /// Language of the chunk the cue came from: detected with `--language auto`,
+    /// otherwise the forced one. `None` for cues read back from an SRT.
+    lang: Option<String>,
+    /// Mean probability whisper gave the cue's text tokens, from 0 to 1. `None`
+    /// for cues read back from an SRT.
+    confidence: Option<f32>,
+    /// Who speaks the cue, from `--per-channel-speakers`. `None` otherwise.
+    speaker: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Word {
+    start_time_cs: u64,
+    end_time_cs: u64,
+    text: String,
 }
 
+/// Format centiseconds as an SRT `HH:MM:SS,mmm` timestamp. Whisper only has
+/// centisecond resolution, so the last millisecond digit is always 0. Hours are
+/// not wrapped at 24 so long recordings keep increasing timestamps.
 fn cs_to_srt_time(cs: u64) -> String {
     let seconds = cs / 100;
     let milliseconds = (cs % 100) * 10; // Convert centiseconds to milliseconds
-    let hours = (seconds / 3600) % 24;
+    let hours = seconds / 3600;
     let minutes = (seconds % 3600) / 60;
     let seconds = seconds % 60;
     format!("{:02}:{:02}:{:02},{:03}", hours, minutes, seconds, milliseconds)
 }
 
+/// The SRT document for `subs`, with CRLF line endings if `crlf` is set since
+/// some broadcast playout systems reject LF-only files.
+fn subtitles_to_srt(subs: &[Subtitle], crlf: bool) -> String {
+    let srt: String = subs.iter().map(subtitle_to_srt).collect();
+    if crlf {
+        srt.replace('\n', "\r\n")
+    } else {
+        srt
+    }
+}
+
 fn subtitle_to_srt(sub: &Subtitle) -> String {
     let start_str = cs_to_srt_time(sub.start_time_cs);
     let end_str = cs_to_srt_time(sub.end_time_cs);
-    format!("{}\n{} --> {}\n{}\n", sub.seq, start_str, end_str, sub.text)
+    // Cues are separated by a blank line
+    format!(
+        "{}\n{} --> {}\n{}\n\n",
+        sub.seq,
+        start_str,
+        end_str,
+        sub.text.trim()
+    )
 }
 
-fn write_raw_transcript(subtitles: &[Subtitle], input_path: &Path) -> Result<(), Box<dyn Error>> {
-    let raw_file_path = format!(
-        "{}_raw.txt",
-        input_path.file_stem().unwrap().to_string_lossy()
-    );
-    let mut out_file = fs::File::create(&raw_file_path)?;
-    for sub in subtitles {
-        out_file.write_all(sub.text.as_bytes())?;
+/// Parse an `HH:MM:SS,mmm` SRT timestamp into centiseconds. The fraction is
+/// read as a decimal, so `.5` is half a second; digits past the third are dropped.
+fn srt_time_to_cs(time: &str) -> Option<u64> {
+    let (hms, fraction) = time.trim().split_once([',', '.'])?;
+    let mut parts = hms.split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
     }
-    Ok(())
+    if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis: u64 = format!("{:0<3.3}", fraction).parse().ok()?;
+    Some((hours * 3600 + minutes * 60 + seconds) * 100 + millis / 10)
 }
 
-fn handle_transcription(
-    whisper_path: &Path,
-    samples: Vec<f32>,
-    chunk_size: usize,
-    input_path: &Path,
-    flash_attn: bool,
-) -> Result<(), Box<dyn Error>> {
-    let ctx = WhisperContext::new_with_params(
-        &whisper_path.to_string_lossy(),
-        WhisperContextParameters {
-            flash_attn,
-            ..Default::default()
-        },
-    )?;
+/// Parse a `--timestamp-base` of `HH:MM:SS`, optionally followed by `,mmm`,
+/// into centiseconds.
+fn parse_timestamp_base(value: &str) -> Result<u64, String> {
+    let time = if value.contains([',', '.']) {
+        value.to_string()
+    } else {
+        format!("{},000", value)
+    };
+    srt_time_to_cs(&time).ok_or_else(|| format!("expected HH:MM:SS[,mmm], got '{}'", value))
+}
 
-    let mut state = ctx.create_state()?;
-    let mut params = FullParams::new(SamplingStrategy::default());
-    params.set_initial_prompt("experience");
+/// A `--replace` rule: every match of `pattern` in a segment's text is replaced.
+#[derive(Clone, Debug)]
+struct Replacement {
+    pattern: regex::Regex,
+    /// May refer to capture groups as `$1` or `${name}`
+    replacement: String,
+}
 
-    let sample_batches = samples.chunks(chunk_size).collect::<Vec<_>>();
-    let chunk_count = sample_batches.len();
+/// Parse `pattern=>replacement`; the replacement may be empty to delete matches.
+fn parse_replacement(value: &str) -> Result<Replacement, String> {
+    let (pattern, replacement) = value
+        .split_once("=>")
+        .ok_or_else(|| format!("expected PATTERN=>REPLACEMENT, got '{}'", value))?;
+    let pattern = regex::Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(Replacement {
+        pattern,
+        replacement: replacement.to_string(),
+    })
+}
 
-    let pb = indicatif::ProgressBar::new(chunk_count as u64);
-    pb.set_style(
-        indicatif::ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    pb.enable_steady_tick(Duration::from_millis(100));
+/// Apply each replacement in order to `text`.
+fn apply_replacements(text: &mut String, replacements: &[Replacement]) {
+    for rule in replacements {
+        if let std::borrow::Cow::Owned(replaced) =
+            rule.pattern.replace_all(text, rule.replacement.as_str())
+        {
+            *text = replaced;
+        }
+    }
+}
 
+/// Parse SRT text back into subtitles; the inverse of `subtitle_to_srt`.
+fn parse_srt(content: &str) -> io::Result<Vec<Subtitle>> {
+    let invalid = |line: usize, msg: &str| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+    };
+
+    let content = content.trim_start_matches('\u{feff}');
     let mut subtitles = Vec::new();
-    let mut seq_number = 1;
-    let mut total_cs = 0;
-
-    for samples in sample_batches {
-        state
-            .full(params.clone(), &samples)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-        let num_segments = state.full_n_segments()?;
-        for i in 0..num_segments {
-            let bytes = state.full_get_segment_bytes(i)?;
-            let segment = String::from_utf8_lossy(&bytes).to_string();
-            let start_timestamp_cs = state.full_get_segment_t0(i)? + total_cs;
-            let end_timestamp_cs = state.full_get_segment_t1(i)? + total_cs;
-
-            subtitles.push(Subtitle {
-                seq: seq_number,
-                start_time_cs: start_timestamp_cs as u64,
-                end_time_cs: end_timestamp_cs as u64,
-                text: segment,
-            });
+    let mut lines = content.lines().map(|line| line.trim_end_matches('\r')).enumerate();
+    while let Some((index, line)) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let seq = line
+            .trim()
+            .parse()
+            .map_err(|_| invalid(index + 1, "expected a sequence number"))?;
+
+        let (index, timing) = lines
+            .next()
+            .ok_or_else(|| invalid(index + 2, "missing timestamp line"))?;
+        let (start, end) = timing
+            .split_once("-->")
+            .and_then(|(start, end)| Some((srt_time_to_cs(start)?, srt_time_to_cs(end)?)))
+            .ok_or_else(|| invalid(index + 1, "invalid timestamp line"))?;
 
-            seq_number += 1;
+        let mut text_lines = Vec::new();
+        for (_, line) in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+            text_lines.push(line.trim());
         }
 
-        total_cs += (chunk_size as f32 / 16000.0 * 100.0) as i64; // Convert chunk size to centiseconds
-        pb.inc(1);
+        subtitles.push(Subtitle {
+            seq,
+            start_time_cs: start,
+            end_time_cs: end,
+            text: text_lines.join(" "),
+            words: Vec::new(),
+            lang: None,
+            confidence: None,
+            speaker: None,
+        });
     }
+    Ok(subtitles)
+}
 
-    pb.finish_with_message("Done");
+/// Whether `time` is exactly `HH:MM:SS,mmm` with minutes and seconds below 60.
+/// Hours may take more than two digits.
+fn is_strict_srt_time(time: &str) -> bool {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let Some((hms, millis)) = time.split_once(',') else {
+        return false;
+    };
+    let [hours, minutes, seconds] = hms.split(':').collect::<Vec<_>>()[..] else {
+        return false;
+    };
+    let below_60 = |part: &str| digits(part) && part.len() == 2 && part < "60";
+    digits(hours)
+        && hours.len() >= 2
+        && below_60(minutes)
+        && below_60(seconds)
+        && digits(millis)
+        && millis.len() == 3
+}
 
-    // Write subtitles to SRT file
-    let srt_file_path = format!(
-        "{}_timestamps.srt",
-        input_path.file_stem().unwrap().to_string_lossy()
-    );
-    let mut out_file_srt = fs::File::create(&srt_file_path)?;
-    for sub in &subtitles {
-        out_file_srt.write_all(subtitle_to_srt(sub).as_bytes())?;
-    }
+/// Re-read a written SRT and check that it is well-formed: sequence numbers run
+/// 1, 2, 3, ..., every timestamp is `HH:MM:SS,mmm`, cues have text, and each
+/// cue starts no earlier than the previous one ends and doesn't end before it
+/// starts. With `allow_overlaps` a cue need only start no earlier than the
+/// previous one, as when several speakers talk at once. Returns every problem
+/// found, each with its line number.
+fn validate_srt(path: &Path, allow_overlaps: bool) -> Result<(), Vec<String>> {
+    let bytes = fs::read(path).map_err(|e| vec![format!("cannot read it: {}", e)])?;
+    let content = decode_text(bytes).ok_or_else(|| vec!["not valid UTF-8".to_string()])?;
 
-    // Write subtitles to _timestamps.txt file
-    let timestamps_file_path = format!(
-        "{}_timestamps.txt",
-        input_path.file_stem().unwrap().to_string_lossy()
-    );
-    let mut out_file_timestamps = fs::File::create(&timestamps_file_path)?;
-    for sub in &subtitles {
-        out_file_timestamps.write_all(
-            format!(
-                "[{} --> {}]: {}\n",
-                cs_to_srt_time(sub.start_time_cs),
-                cs_to_srt_time(sub.end_time_cs),
-                sub.text
-            )
-            .as_bytes(),
-        )?;
-    }
+    let mut problems = Vec::new();
+    let mut lines = content.lines().map(|line| line.trim_end_matches('\r')).enumerate();
+    let mut expected_seq = 1;
+    let mut previous: Option<(u64, u64)> = None;
+    while let Some((index, line)) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = index + 1;
+        // Carry on from a misnumbered cue so one gap is reported once
+        match line.trim().parse::<u32>() {
+            Ok(seq) => {
+                if seq != expected_seq {
+                    problems.push(format!(
+                        "line {}: cue {} should be numbered {}",
+                        line_no, seq, expected_seq
+                    ));
+                }
+                expected_seq = seq + 1;
+            }
+            Err(_) => {
+                problems.push(format!("line {}: expected a sequence number", line_no));
+                expected_seq += 1;
+            }
+        }
 
-    // Write raw transcript to raw.txt file
-    match write_raw_transcript(&subtitles, input_path) {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to write raw transcript: {}", e);
-            std::process::exit(1);
+        let Some((index, timing)) = lines.next() else {
+            problems.push(format!("line {}: missing timestamp line", line_no + 1));
+            break;
+        };
+        let times = timing
+            .split_once(" --> ")
+            .filter(|(start, end)| is_strict_srt_time(start) && is_strict_srt_time(end))
+            .and_then(|(start, end)| Some((srt_time_to_cs(start)?, srt_time_to_cs(end)?)));
+        match times {
+            None => {
+                problems.push(format!("line {}: invalid timestamp line '{}'", index + 1, timing))
+            }
+            Some((start, end)) => {
+                if end < start {
+                    problems.push(format!("line {}: cue ends before it starts", index + 1));
+                }
+                if let Some((previous_start, previous_end)) = previous {
+                    if start < previous_start {
+                        problems.push(format!(
+                            "line {}: cue starts before the previous one",
+                            index + 1
+                        ));
+                    } else if start < previous_end && !allow_overlaps {
+                        problems
+                            .push(format!("line {}: cue overlaps the previous one", index + 1));
+                    }
+                }
+                previous = Some((start, end));
+            }
         }
-    }
 
-    Ok(())
+        let mut has_text = false;
+        for (_, line) in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+            has_text = true;
+        }
+        if !has_text {
+            problems.push(format!("line {}: cue has no text", line_no));
+        }
+    }
+    if problems.is_empty() { Ok(()) } else { Err(problems) }
 }
 
-
-// Usage: {} <path_to_wav_file> [model_path]
-#[derive(Parser)]
-struct Args {
-    #[arg(help = "Path to the audio containing file", required = true, num_args = 1..)]
-    audio_paths: Vec<String>, // Paths to the audio files
-    #[arg(help = "Path to the model")]
-    model_path: Option<String>, // Path to the model
-    #[arg(long, help = "Use flash attention")]
-    fa: bool, // Use flash attention
+/// Drop subtitles whose text is empty or whitespace-only, which would otherwise
+/// produce SRT cues with blank bodies.
+fn drop_empty_subtitles(subtitles: &mut Vec<Subtitle>) {
+    subtitles.retain(|sub| !sub.text.trim().is_empty());
+    renumber_subtitles(subtitles);
 }
 
-fn main() {
-    let args = Args::parse();
-
-    // Introduce a temporary binding for the default model path
-    let binding = "ggml-large-v3-turbo.bin".to_string();
+/// Pause between cues long enough to end a sentence that has no closing
+/// punctuation, so a trailing fragment isn't joined to speech much later.
+const SENTENCE_GAP_CS: u64 = 150; // 1.5 seconds
 
-    // Use the temporary binding in unwrap_or
-    let model_path = args.model_path.unwrap_or(binding);
-    let whisper_path = Path::new(&model_path);
-    if !whisper_path.exists() {
-        eprintln!("Model not found at {}", whisper_path.display());
-        std::process::exit(1);
+/// Re-split subtitles so each cue holds one sentence. A sentence ends at a word
+/// ending in `.`, `?` or `!` (optionally followed by closing quotes or brackets)
+/// when the next word starts with a capital letter, or where the next cue starts
+/// more than `SENTENCE_GAP_CS` after the previous one ends. Word times come
+/// from the cue's token timestamps while they match its text and are otherwise
+/// interpolated linearly within the cue; the timed words are kept.
+fn resegment_sentences(subtitles: &[Subtitle]) -> Vec<Subtitle> {
+    let mut words = Vec::new();
+    for sub in subtitles {
+        let duration_cs = sub.end_time_cs.saturating_sub(sub.start_time_cs);
+        let sub_words: Vec<&str> = sub.text.split_whitespace().collect();
+        let timed = sub.words.iter().map(|word| word.text.as_str()).eq(sub_words.iter().copied());
+        let count = sub_words.len() as u64;
+        for (i, word) in sub_words.into_iter().enumerate() {
+            let timed_word = if timed { sub.words.get(i) } else { None };
+            let i = i as u64;
+            let (start_cs, end_cs) = match timed_word {
+                Some(timed_word) => (timed_word.start_time_cs, timed_word.end_time_cs),
+                None => (
+                    sub.start_time_cs + duration_cs * i / count,
+                    sub.start_time_cs + duration_cs * (i + 1) / count,
+                ),
+            };
+            words.push((word, start_cs, end_cs, timed_word, sub));
+        }
     }
 
-    // Download FFmpeg if not already installed
-    match download_ffmpeg() {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to download FFmpeg: {}", e);
-            std::process::exit(1);
+    let ends_sentence = |word: &str| {
+        word.trim_end_matches(['"', '\'', ')', ']', '\u{201d}'])
+            .ends_with(['.', '?', '!'])
+    };
+    let mut result = Vec::new();
+    let mut sentence: Vec<&str> = Vec::new();
+    let mut timed_words: Vec<Option<&Word>> = Vec::new();
+    let mut start_cs = 0;
+    let mut lang = &None;
+    let mut confidence = None;
+    let mut speaker = &None;
+    for (i, &(word, word_start_cs, word_end_cs, timed_word, sub)) in words.iter().enumerate() {
+        if sentence.is_empty() {
+            start_cs = word_start_cs;
+            lang = &sub.lang;
+            confidence = sub.confidence;
+            speaker = &sub.speaker;
+        }
+        confidence = lower_confidence(confidence, sub.confidence);
+        sentence.push(word);
+        timed_words.push(timed_word);
+        let next = words.get(i + 1);
+        let next_is_capitalised = next
+            .and_then(|(next, ..)| next.chars().find(|c| c.is_alphabetic()))
+            .is_none_or(char::is_uppercase);
+        let pause_follows = next.is_some_and(|&(.., next_sub)| {
+            !std::ptr::eq(next_sub, sub)
+                && next_sub.start_time_cs > sub.end_time_cs + SENTENCE_GAP_CS
+        });
+        if (ends_sentence(word) && next_is_capitalised) || pause_follows || next.is_none() {
+            // Only keep word times when every word of the sentence has them
+            let words = timed_words.drain(..).map(|word| word.cloned()).collect::<Option<_>>();
+            result.push(Subtitle {
+                seq: 0,
+                start_time_cs: start_cs,
+                end_time_cs: word_end_cs,
+                text: sentence.join(" "),
+                words: words.unwrap_or_default(),
+                lang: lang.clone(),
+                confidence,
+                speaker: speaker.clone(),
+            });
+            sentence.clear();
         }
     }
+    renumber_subtitles(&mut result);
+    result
+}
+
+/// The confidence of a cue joined from two others: the lower one, so joining
+/// never hides an uncertain part.
+fn lower_confidence(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
 
-    // Process each audio file
-    for audio_path_str in &args.audio_paths {
-        let audio_path = Path::new(audio_path_str);
-        if !audio_path.exists() {
-            eprintln!("Error: Audio file does not exist at {}", audio_path_str);
+/// Split subtitles longer than `max_cs` into several cues, apportioning the
+/// words evenly and interpolating timestamps linearly by word count.
+fn split_long_subtitles(subtitles: Vec<Subtitle>, max_cs: u64) -> Vec<Subtitle> {
+    let mut result = Vec::with_capacity(subtitles.len());
+    for sub in subtitles {
+        let duration_cs = sub.end_time_cs.saturating_sub(sub.start_time_cs);
+        let words: Vec<&str> = sub.text.split_whitespace().collect();
+        if max_cs == 0 || duration_cs <= max_cs || words.len() < 2 {
+            result.push(sub);
             continue;
         }
 
-        // Create temp directory per file
-        let temp_dir = match create_temporary_directory() {
-            Ok(dir) => dir,
-            Err(e) => {
-                eprintln!("Failed to create temporary directory: {}", e);
-                continue;
-            }
+        let parts = (duration_cs.div_ceil(max_cs) as usize).min(words.len());
+        let time_at = |word_index: usize| {
+            sub.start_time_cs + duration_cs * word_index as u64 / words.len() as u64
         };
+        for part in 0..parts {
+            let first = part * words.len() / parts;
+            let last = (part + 1) * words.len() / parts;
+            result.push(Subtitle {
+                seq: 0,
+                start_time_cs: time_at(first),
+                end_time_cs: time_at(last),
+                text: words[first..last].join(" "),
+                words: Vec::new(),
+                lang: sub.lang.clone(),
+                confidence: sub.confidence,
+                speaker: sub.speaker.clone(),
+            });
+        }
+    }
+    renumber_subtitles(&mut result);
+    result
+}
 
-        let output_path = temp_dir.path().join("converted_audio.wav");
+/// Merge subtitles shorter than `min_cs` into the following cue, or into the
+/// previous one when they are last, joining their text and time ranges.
+fn merge_short_subtitles(subtitles: Vec<Subtitle>, min_cs: u64) -> Vec<Subtitle> {
+    fn join(first: Subtitle, second: Subtitle) -> Subtitle {
+        Subtitle {
+            seq: 0,
+            start_time_cs: first.start_time_cs,
+            end_time_cs: second.end_time_cs.max(first.end_time_cs),
+            text: format!("{} {}", first.text.trim(), second.text.trim()),
+            words: [first.words, second.words].concat(),
+            lang: first.lang.or(second.lang),
+            confidence: lower_confidence(first.confidence, second.confidence),
+            speaker: first.speaker.or(second.speaker),
+        }
+    }
+    let is_short = |sub: &Subtitle| sub.end_time_cs.saturating_sub(sub.start_time_cs) < min_cs;
 
-        // Ensure WAV compatibility
-        match ensure_wav_compatibility(audio_path, &output_path) {
-            Ok(_) => (),
+    let mut result: Vec<Subtitle> = Vec::with_capacity(subtitles.len());
+    let mut pending: Option<Subtitle> = None;
+    for sub in subtitles {
+        let sub = match pending.take() {
+            Some(short) => join(short, sub),
+            None => sub,
+        };
+        if is_short(&sub) {
+            pending = Some(sub);
+        } else {
+            result.push(sub);
+        }
+    }
+    if let Some(short) = pending {
+        match result.pop() {
+            Some(previous) => result.push(join(previous, short)),
+            None => result.push(short),
+        }
+    }
+    renumber_subtitles(&mut result);
+    result
+}
+
+/// Shift every subtitle by `offset_cs` (which may be negative), clamping at zero.
+fn offset_subtitles(subtitles: &mut [Subtitle], offset_cs: i64) {
+    let shift = |time_cs: u64| time_cs.saturating_add_signed(offset_cs);
+    for sub in subtitles {
+        sub.start_time_cs = shift(sub.start_time_cs);
+        sub.end_time_cs = shift(sub.end_time_cs);
+        for word in &mut sub.words {
+            word.start_time_cs = shift(word.start_time_cs);
+            word.end_time_cs = shift(word.end_time_cs);
+        }
+    }
+}
+
+/// Reassign sequence numbers so they run contiguously from 1.
+fn renumber_subtitles(subtitles: &mut [Subtitle]) {
+    for (i, sub) in subtitles.iter_mut().enumerate() {
+        sub.seq = i as u32 + 1;
+    }
+}
+
+/// Paths of the files written for a single input.
+struct OutputPaths {
+    raw: PathBuf,
+    srt: PathBuf,
+    timestamps: PathBuf,
+    converted_wav: PathBuf,
+    /// SRT files written instead of `srt` when `--split-output` is used
+    srt_parts: Vec<PathBuf>,
+    /// The file named by `--output`, written verbatim for its format
+    output: Option<(OutputFormat, PathBuf)>,
+    namer: FileNamer,
+}
+
+/// Builds the output file names for one input from its stem and a suffix and
+/// extension per kind of file.
+struct FileNamer {
+    stem: std::ffi::OsString,
+    template: Option<String>,
+    model: String,
+    lang: String,
+    /// Whether `.<lang>` goes before the extension of built-in names (`--lang-suffix`)
+    lang_suffix: bool,
+    date: String,
+    /// Counter added before the extension to avoid overwriting (`--no-overwrite`)
+    number: Option<u32>,
+}
+
+impl FileNamer {
+    // Names are built on the raw OsStr so non-UTF8 stems survive intact
+    fn name(&self, suffix: &str, ext: &str) -> PathBuf {
+        let mut name = match &self.template {
+            Some(template) => {
+                // Expand around {stem} by hand since it may not be valid UTF-8
+                let expand = |part: &str| {
+                    part.replace("{ext}", ext)
+                        .replace("{model}", &self.model)
+                        .replace("{lang}", &self.lang)
+                        .replace("{date}", &self.date)
+                };
+                let mut name = std::ffi::OsString::new();
+                for (i, part) in template.split("{stem}").enumerate() {
+                    if i > 0 {
+                        name.push(&self.stem);
+                    }
+                    name.push(expand(part));
+                }
+                name
+            }
+            None => {
+                let mut name = self.stem.clone();
+                name.push(suffix);
+                if self.lang_suffix {
+                    name.push(".");
+                    name.push(&self.lang);
+                }
+                name.push(".");
+                name.push(ext);
+                name
+            }
+        };
+        if let Some(n) = self.number {
+            // Insert the counter before the extension
+            let path = PathBuf::from(&name);
+            let mut numbered = path.file_stem().unwrap_or_default().to_os_string();
+            numbered.push(format!(" ({})", n));
+            if let Some(ext) = path.extension() {
+                numbered.push(".");
+                numbered.push(ext);
+            }
+            name = path.with_file_name(numbered).into_os_string();
+        }
+        PathBuf::from(name)
+    }
+}
+
+/// How output file names are built: either the built-in `<stem>_<kind>.<ext>`
+/// names or a user template with `{stem}`, `{ext}`, `{model}`, `{lang}` and
+/// `{date}` placeholders.
+struct OutputNaming {
+    template: Option<String>,
+    model: String,
+    lang: String,
+    lang_suffix: bool,
+    no_overwrite: bool,
+    output: Option<PathBuf>,
+    /// The formats `write_outputs` writes, which `--no-overwrite` must not replace
+    writers: Vec<(OutputFormat, Box<dyn SubtitleWriter>)>,
+    /// Whether the SRT is written as `--split-output` parts
+    split_output: bool,
+}
+
+impl OutputNaming {
+    fn new(args: &Args, model_path: &Path, lang: &str) -> Self {
+        OutputNaming {
+            template: args.out.output_template.clone(),
+            model: model_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            lang: lang.to_string(),
+            // Until a language was detected there is nothing to add
+            lang_suffix: args.out.lang_suffix && lang != "auto",
+            no_overwrite: args.out.no_overwrite,
+            output: args.out.output.clone(),
+            writers: output_formats(args)
+                .into_iter()
+                .map(|format| (format, format.writer(args)))
+                .collect(),
+            split_output: args.out.split_output.is_some(),
+        }
+    }
+}
+
+impl OutputPaths {
+    /// Output paths for `input_path`. A `tag` is appended to the stem (e.g. the
+    /// model name when benchmarking). With `no_overwrite`, if any file that
+    /// will be written already exists the first `<name> (n).<ext>` variant for
+    /// which none do is used.
+    fn new(
+        input_path: &Path,
+        tag: Option<&str>,
+        naming: &OutputNaming,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut outputs = Self::numbered(input_path, tag, naming, None)?;
+        let mut n = 0;
+        while naming.no_overwrite && outputs.written_files(naming).iter().any(|path| path.exists())
+        {
+            n += 1;
+            outputs = Self::numbered(input_path, tag, naming, Some(n))?;
+        }
+        Ok(outputs)
+    }
+
+    /// The files written for the formats in `naming`. Of a split SRT only the
+    /// first part is listed, since every split run writes it.
+    fn written_files(&self, naming: &OutputNaming) -> Vec<PathBuf> {
+        naming
+            .writers
+            .iter()
+            .map(|(format, writer)| match format {
+                OutputFormat::Srt if naming.split_output => self.srt_part(1),
+                _ => self.path(*format, &**writer),
+            })
+            .collect()
+    }
+
+    /// Path of part `n` of a split SRT: `_partNNN` is added before the extension.
+    fn srt_part(&self, n: usize) -> PathBuf {
+        let mut name = self.srt.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("_part{:03}", n));
+        if let Some(ext) = self.srt.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        self.srt.with_file_name(name)
+    }
+
+    fn numbered(
+        input_path: &Path,
+        tag: Option<&str>,
+        naming: &OutputNaming,
+        number: Option<u32>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut stem = output_base(input_path);
+        if let Some(tag) = tag {
+            stem.push("_");
+            stem.push(tag);
+        }
+        let namer = FileNamer {
+            stem,
+            template: naming.template.clone(),
+            model: naming.model.clone(),
+            lang: naming.lang.clone(),
+            lang_suffix: naming.lang_suffix,
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            number,
+        };
+
+        // With a template every output shares it, so the kind goes into {ext}
+        let (raw_ext, wav_ext) = if naming.template.is_some() {
+            ("raw.txt", "16k.wav")
+        } else {
+            ("txt", "wav")
+        };
+        let output = naming
+            .output
+            .as_ref()
+            .and_then(|path| Some((output_path_format(path)?, path.clone())));
+        let mut outputs = OutputPaths {
+            raw: namer.name("_raw", raw_ext),
+            srt: namer.name("_timestamps", "srt"),
+            timestamps: namer.name("_timestamps", "txt"),
+            converted_wav: namer.name("_16k", wav_ext),
+            srt_parts: Vec::new(),
+            output: None,
+            namer,
+        };
+        // Steps that read the SRT or raw transcript back then find the named file
+        match &output {
+            Some((OutputFormat::Raw, path)) => outputs.raw = path.clone(),
+            Some((OutputFormat::Srt, path)) => outputs.srt = path.clone(),
+            _ => {}
+        }
+        outputs.output = output;
+        Ok(outputs)
+    }
+
+    /// Path of the file written for `format`. The raw transcript, SRT and
+    /// timestamps keep their own names; other formats are `<stem>_transcript.<ext>`.
+    /// The format `--output` selected is written to the path it names.
+    fn path(&self, format: OutputFormat, writer: &dyn SubtitleWriter) -> PathBuf {
+        if let Some((output_format, path)) = &self.output
+            && *output_format == format
+        {
+            return path.clone();
+        }
+        match format {
+            OutputFormat::Raw => self.raw.clone(),
+            OutputFormat::Srt => self.srt.clone(),
+            OutputFormat::Txt => self.timestamps.clone(),
+            OutputFormat::Heatmap => self.namer.name("_heatmap", writer.extension()),
+            _ => self.namer.name("_transcript", writer.extension()),
+        }
+    }
+}
+
+/// Stem that output files for `input_path` are named after. Inputs without a
+/// usable one, such as `.wav`, `..` or a directory-like `/tmp/`, fall back to
+/// `transcript`.
+fn output_base(input_path: &Path) -> std::ffi::OsString {
+    let fallback = || std::ffi::OsString::from("transcript");
+    let raw = input_path.as_os_str().as_encoded_bytes();
+    if raw.ends_with(b"/") || (cfg!(windows) && raw.ends_with(b"\\")) {
+        return fallback();
+    }
+    let (Some(name), Some(stem)) = (input_path.file_name(), input_path.file_stem()) else {
+        return fallback();
+    };
+    // A name like `.wav` parses as a dotfile stem but is only an extension
+    let name = name.as_encoded_bytes();
+    if name.starts_with(b".") && !name[1..].contains(&b'.') {
+        return fallback();
+    }
+    stem.to_os_string()
+}
+
+/// Partition subtitles into consecutive windows of `part_cs` by start time.
+/// Every window up to the last cue gets a part, even an empty one, so part
+/// numbers always match the time they cover. Each part is numbered from 1 and,
+/// with `rebase`, its times are made relative to the start of its window.
+fn split_subtitles_by_time(
+    subtitles: &[Subtitle],
+    part_cs: u64,
+    rebase: bool,
+) -> Vec<Vec<Subtitle>> {
+    let part_cs = part_cs.max(1);
+    let mut parts: Vec<Vec<Subtitle>> = Vec::new();
+    for sub in subtitles {
+        let index = (sub.start_time_cs / part_cs) as usize;
+        if parts.len() <= index {
+            parts.resize_with(index + 1, Vec::new);
+        }
+        let mut sub = sub.clone();
+        if rebase {
+            let part_start_cs = index as u64 * part_cs;
+            sub.start_time_cs -= part_start_cs;
+            sub.end_time_cs = sub.end_time_cs.saturating_sub(part_start_cs);
+        }
+        parts[index].push(sub);
+    }
+    for part in &mut parts {
+        renumber_subtitles(part);
+    }
+    parts
+}
+
+/// Join segment texts into the raw transcript. With `marker_interval_cs`, an
+/// `[HH:MM:SS]` anchor is placed before the first segment and before every
+/// segment that reaches the next interval boundary.
+fn raw_transcript_text(subtitles: &[Subtitle], marker_interval_cs: Option<u64>) -> String {
+    let mut parts = Vec::with_capacity(subtitles.len());
+    let marker_interval_cs = marker_interval_cs.filter(|&interval| interval > 0);
+    let mut next_boundary_cs = 0;
+    for sub in subtitles {
+        if let Some(interval_cs) = marker_interval_cs
+            && sub.end_time_cs >= next_boundary_cs
+        {
+            let secs = sub.start_time_cs / 100;
+            parts.push(format!(
+                "[{:02}:{:02}:{:02}]",
+                secs / 3600,
+                (secs % 3600) / 60,
+                secs % 60
+            ));
+            next_boundary_cs = (sub.end_time_cs / interval_cs + 1) * interval_cs;
+        }
+        parts.push(sub.text.trim().to_string());
+    }
+    parts.join(" ")
+}
+
+/// Transcript formats selected with `--format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum OutputFormat {
+    /// The plain transcript (<stem>_raw.txt)
+    Raw,
+    /// SubRip subtitles (<stem>_timestamps.srt)
+    Srt,
+    /// `[start --> end]: text` lines (<stem>_timestamps.txt)
+    Txt,
+    /// One row per segment (<stem>_transcript.csv)
+    Csv,
+    /// whisper.cpp's `start<TAB>end<TAB>text` layout with millisecond times
+    Tsv,
+    /// Advanced SubStation Alpha subtitles (<stem>_transcript.ass)
+    Ass,
+    /// WebVTT subtitles (<stem>_transcript.vtt)
+    Vtt,
+    /// A JSON array of segments with millisecond times (<stem>_transcript.json)
+    Json,
+    /// `[mm:ss.cc]` lyrics lines (<stem>_transcript.lrc)
+    Lrc,
+    /// HTML timeline of segments coloured by confidence (<stem>_heatmap.html)
+    Heatmap,
+    /// Every format above
+    All,
+    /// No files at all, e.g. with --stream-jsonl; wins over any other --format
+    #[value(name = "none")]
+    NoFiles,
+}
+
+impl OutputFormat {
+    /// The formats that write a file, which `all` stands for.
+    const FILES: [OutputFormat; 10] = [
+        OutputFormat::Raw,
+        OutputFormat::Srt,
+        OutputFormat::Txt,
+        OutputFormat::Csv,
+        OutputFormat::Tsv,
+        OutputFormat::Ass,
+        OutputFormat::Vtt,
+        OutputFormat::Json,
+        OutputFormat::Lrc,
+        OutputFormat::Heatmap,
+    ];
+
+    /// The format `--output` writes for a file with this extension. `.txt` is
+    /// the raw transcript.
+    fn from_extension(ext: &str) -> Option<OutputFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "txt" => Some(OutputFormat::Raw),
+            "srt" => Some(OutputFormat::Srt),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            "ass" => Some(OutputFormat::Ass),
+            "vtt" => Some(OutputFormat::Vtt),
+            "json" => Some(OutputFormat::Json),
+            "lrc" => Some(OutputFormat::Lrc),
+            "html" => Some(OutputFormat::Heatmap),
+            _ => None,
+        }
+    }
+
+    /// The writer for this format, configured from the output options in `args`.
+    fn writer(self, args: &Args) -> Box<dyn SubtitleWriter> {
+        match self {
+            OutputFormat::Raw => Box::new(RawWriter {
+                marker_interval_cs: args.out.raw_timestamp_interval.map(|secs| secs * 100),
+            }),
+            OutputFormat::Srt => Box::new(SrtWriter {
+                crlf: args.out.crlf,
+                color_speakers: if args.color_speakers {
+                    args.per_channel_speakers.clone()
+                } else {
+                    Vec::new()
+                },
+            }),
+            OutputFormat::Txt => Box::new(TimestampsWriter),
+            OutputFormat::Csv => Box::new(CsvWriter { fps: args.out.fps }),
+            OutputFormat::Tsv => Box::new(TsvWriter),
+            OutputFormat::Ass => Box::new(AssWriter { karaoke: args.out.karaoke }),
+            OutputFormat::Vtt => Box::new(VttWriter),
+            OutputFormat::Json => Box::new(JsonWriter),
+            OutputFormat::Lrc => Box::new(LrcWriter),
+            OutputFormat::Heatmap => Box::new(HeatmapWriter),
+            OutputFormat::All | OutputFormat::NoFiles => {
+                unreachable!("expanded by output_formats")
+            }
+        }
+    }
+
+    /// Name used in messages, e.g. "CSV output written to ...".
+    fn label(self) -> &'static str {
+        match self {
+            OutputFormat::Raw => "Raw",
+            OutputFormat::Srt => "SRT",
+            OutputFormat::Txt => "Timestamped",
+            OutputFormat::Csv => "CSV",
+            OutputFormat::Tsv => "TSV",
+            OutputFormat::Ass => "ASS",
+            OutputFormat::Vtt => "WebVTT",
+            OutputFormat::Json => "JSON",
+            OutputFormat::Lrc => "LRC",
+            OutputFormat::Heatmap => "Confidence heatmap",
+            OutputFormat::All | OutputFormat::NoFiles => {
+                unreachable!("expanded by output_formats")
+            }
+        }
+    }
+}
+
+/// The formats to write: `--format`, or raw, SRT and timestamps when it isn't
+/// given, plus CSV for `--csv` and the heatmap for `--confidence-heatmap`.
+/// `all` expands to every format and `none` to none at all. `--output` adds
+/// its own format, and is the only one written when `--format` isn't given.
+/// `--no-timestamps` leaves out the timed formats.
+fn output_formats(args: &Args) -> Vec<OutputFormat> {
+    if args.out.format.contains(&OutputFormat::NoFiles) {
+        return Vec::new();
+    }
+    let output_format = args.out.output.as_deref().and_then(output_path_format);
+    let mut formats = if args.out.format.is_empty() {
+        match output_format {
+            Some(_) => Vec::new(),
+            None => vec![OutputFormat::Raw, OutputFormat::Srt, OutputFormat::Txt],
+        }
+    } else if args.out.format.contains(&OutputFormat::All) {
+        OutputFormat::FILES.to_vec()
+    } else {
+        args.out.format.clone()
+    };
+    formats.extend(output_format);
+    if args.out.csv {
+        formats.push(OutputFormat::Csv);
+    }
+    if args.out.confidence_heatmap {
+        formats.push(OutputFormat::Heatmap);
+    }
+    // Without timestamps every format but the raw transcript would be empty
+    if args.no_timestamps {
+        formats.retain(|format| *format == OutputFormat::Raw);
+    }
+    formats.sort();
+    formats.dedup();
+    formats
+}
+
+/// The format `--output` selects for `path` from its extension.
+fn output_path_format(path: &Path) -> Option<OutputFormat> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(OutputFormat::from_extension)
+}
+
+fn parse_output_path(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    match output_path_format(&path) {
+        Some(_) => Ok(path),
+        None => Err(format!(
+            "can't tell the format of '{}'; use .srt, .vtt, .json, .txt, .csv, .lrc, .ass, \
+             .tsv or .html",
+            value
+        )),
+    }
+}
+
+/// Serializes subtitles into one output format. Adding a format means adding
+/// an implementation and an `OutputFormat` variant that selects it.
+trait SubtitleWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()>;
+    /// Extension of the file the output is written to.
+    fn extension(&self) -> &str;
+}
+
+struct RawWriter {
+    marker_interval_cs: Option<u64>,
+}
+
+impl SubtitleWriter for RawWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(raw_transcript_text(subs, self.marker_interval_cs).as_bytes())
+    }
+
+    fn extension(&self) -> &str {
+        "txt"
+    }
+}
+
+struct SrtWriter {
+    crlf: bool,
+    // Speakers to colour, in --per-channel-speakers order; empty to leave cues as they are
+    color_speakers: Vec<String>,
+}
+
+impl SubtitleWriter for SrtWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        if !self.color_speakers.is_empty() {
+            let coloured = colour_speakers(subs, &self.color_speakers);
+            return out.write_all(subtitles_to_srt(&coloured, self.crlf).as_bytes());
+        }
+        out.write_all(subtitles_to_srt(subs, self.crlf).as_bytes())
+    }
+
+    fn extension(&self) -> &str {
+        "srt"
+    }
+}
+
+struct TimestampsWriter;
+
+impl SubtitleWriter for TimestampsWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        for sub in subs {
+            writeln!(
+                out,
+                "[{} --> {}]: {}",
+                cs_to_srt_time(sub.start_time_cs),
+                cs_to_srt_time(sub.end_time_cs),
+                sub.text.trim()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        "txt"
+    }
+}
+
+struct CsvWriter {
+    fps: Option<f32>,
+}
+
+impl SubtitleWriter for CsvWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        write_csv_transcript(subs, out, self.fps)
+    }
+
+    fn extension(&self) -> &str {
+        "csv"
+    }
+}
+
+struct TsvWriter;
+
+impl SubtitleWriter for TsvWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(subtitles_to_tsv(subs).as_bytes())
+    }
+
+    fn extension(&self) -> &str {
+        "tsv"
+    }
+}
+
+struct VttWriter;
+
+impl SubtitleWriter for VttWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "WEBVTT")?;
+        for sub in subs {
+            // A blank line would end the cue early and an arrow would start a new one
+            let text = sub
+                .text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.trim().replace("-->", "--&gt;"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            writeln!(
+                out,
+                "\n{} --> {}\n{}",
+                cs_to_srt_time(sub.start_time_cs).replace(',', "."),
+                cs_to_srt_time(sub.end_time_cs).replace(',', "."),
+                text
+            )?;
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        "vtt"
+    }
+}
+
+/// The segments as a JSON array of the objects `--stream-jsonl` prints.
+struct JsonWriter;
+
+impl SubtitleWriter for JsonWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        let segments: Vec<serde_json::Value> = subs.iter().map(segment_json).collect();
+        serde_json::to_writer_pretty(&mut *out, &segments)?;
+        writeln!(out)
+    }
+
+    fn extension(&self) -> &str {
+        "json"
+    }
+}
+
+/// One `[mm:ss.cc]text` line per cue, as music players show lyrics.
+struct LrcWriter;
+
+impl SubtitleWriter for LrcWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        for sub in subs {
+            let seconds = sub.start_time_cs / 100;
+            writeln!(
+                out,
+                "[{:02}:{:02}.{:02}]{}",
+                seconds / 60,
+                seconds % 60,
+                sub.start_time_cs % 100,
+                sub.text.trim().replace('\n', " ")
+            )?;
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        "lrc"
+    }
+}
+
+struct AssWriter {
+    karaoke: bool,
+}
+
+/// Script header with a single bottom-centred `Default` style.
+const ASS_HEADER: &str = "\
+[Script Info]
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+WrapStyle: 0
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, \
+Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,64,&H00FFFFFF,&H0000FFFF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,\
+0,2,60,60,50,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+impl SubtitleWriter for AssWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(ASS_HEADER.as_bytes())?;
+        for sub in subs {
+            let text = if self.karaoke {
+                karaoke_text(sub)
+            } else {
+                ass_escape(sub.text.trim())
+            };
+            writeln!(
+                out,
+                "Dialogue: 0,{},{},Default,,0,0,0,,{}",
+                cs_to_ass_time(sub.start_time_cs),
+                cs_to_ass_time(sub.end_time_cs),
+                text
+            )?;
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        "ass"
+    }
+}
+
+/// Format centiseconds as an ASS `H:MM:SS.cc` timestamp.
+fn cs_to_ass_time(cs: u64) -> String {
+    let seconds = cs / 100;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60,
+        cs % 100
+    )
+}
+
+/// Braces start override tags in ASS, so they can't appear in plain text.
+fn ass_escape(text: &str) -> String {
+    text.replace('{', "(").replace('}', ")").replace('\n', "\\N")
+}
+
+/// The cue's text with a `{\kNN}` tag before each word giving its duration in
+/// centiseconds, so players highlight words as they're spoken. Pauses between
+/// words become empty `\k` syllables. The token timestamps are used while they
+/// still match the text; after a step that changed the text, word times are
+/// spread evenly over the cue instead.
+fn karaoke_text(sub: &Subtitle) -> String {
+    let text_words: Vec<&str> = sub.text.split_whitespace().collect();
+    let timed = sub.words.iter().map(|word| word.text.as_str()).eq(text_words.iter().copied());
+    let words: Vec<Word> = if timed {
+        sub.words.clone()
+    } else {
+        let duration_cs = sub.end_time_cs.saturating_sub(sub.start_time_cs);
+        let count = text_words.len() as u64;
+        let time_at = |i: u64| sub.start_time_cs + duration_cs * i / count.max(1);
+        (0..count)
+            .map(|i| Word {
+                start_time_cs: time_at(i),
+                end_time_cs: time_at(i + 1),
+                text: text_words[i as usize].to_string(),
+            })
+            .collect()
+    };
+
+    let mut parts = Vec::with_capacity(words.len());
+    let mut cursor_cs = sub.start_time_cs;
+    for word in &words {
+        let start_cs = word.start_time_cs.max(cursor_cs);
+        let end_cs = word.end_time_cs.min(sub.end_time_cs).max(start_cs);
+        let gap_cs = start_cs - cursor_cs;
+        let pause = if gap_cs > 0 {
+            format!("{{\\k{}}}", gap_cs)
+        } else {
+            String::new()
+        };
+        parts.push(format!("{}{{\\k{}}}{}", pause, end_cs - start_cs, ass_escape(&word.text)));
+        cursor_cs = end_cs;
+    }
+    parts.join(" ")
+}
+
+/// Confidence below which a segment is listed as needing review.
+const LOW_CONFIDENCE: f32 = 0.5;
+
+/// A standalone HTML page with a timeline of the segments coloured from red
+/// (uncertain) to green (confident), each showing its text on hover and
+/// linking to its row in a table of the segments below.
+struct HeatmapWriter;
+
+impl SubtitleWriter for HeatmapWriter {
+    fn write(&self, subs: &[Subtitle], out: &mut dyn Write) -> io::Result<()> {
+        let total_cs = subs.iter().map(|sub| sub.end_time_cs).max().unwrap_or(0).max(1);
+        let low = subs
+            .iter()
+            .filter(|sub| sub.confidence.is_some_and(|c| c < LOW_CONFIDENCE))
+            .count();
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html><head><meta charset=\"utf-8\"><title>Confidence</title>")?;
+        writeln!(
+            out,
+            "<style>body{{font-family:sans-serif}}svg{{width:100%;height:60px}}\
+             td{{padding:2px 8px}}a{{color:inherit}}</style></head><body>"
+        )?;
+        writeln!(
+            out,
+            "<p>{} segments, {} below {:.0}% confidence</p>",
+            subs.len(),
+            low,
+            LOW_CONFIDENCE * 100.0
+        )?;
+        writeln!(out, "<svg viewBox=\"0 0 1000 60\" preserveAspectRatio=\"none\">")?;
+        for sub in subs {
+            let x = sub.start_time_cs as f64 * 1000.0 / total_cs as f64;
+            let width = sub.end_time_cs.saturating_sub(sub.start_time_cs) as f64 * 1000.0
+                / total_cs as f64;
+            writeln!(
+                out,
+                "<a href=\"#s{}\"><rect x=\"{:.2}\" width=\"{:.2}\" height=\"60\" fill=\"{}\">\
+                 <title>{}</title></rect></a>",
+                sub.seq,
+                x,
+                width.max(0.5),
+                confidence_colour(sub.confidence),
+                html_escape(&heatmap_label(sub))
+            )?;
+        }
+        writeln!(out, "</svg>")?;
+        writeln!(out, "<table>")?;
+        for sub in subs {
+            writeln!(
+                out,
+                "<tr id=\"s{}\" style=\"background:{}\"><td>{}</td><td>{}</td><td>{}</td></tr>",
+                sub.seq,
+                confidence_colour(sub.confidence),
+                cs_to_srt_time(sub.start_time_cs),
+                sub.confidence.map_or("-".to_string(), |c| format!("{:.0}%", c * 100.0)),
+                html_escape(sub.text.trim())
+            )?;
+        }
+        writeln!(out, "</table></body></html>")
+    }
+
+    fn extension(&self) -> &str {
+        "html"
+    }
+}
+
+/// Pale red through yellow to green as `confidence` goes from 0 to 1; grey when unknown.
+fn confidence_colour(confidence: Option<f32>) -> String {
+    match confidence {
+        Some(c) => format!("hsl({:.0},70%,75%)", c.clamp(0.0, 1.0) * 120.0),
+        None => "#ccc".to_string(),
+    }
+}
+
+/// Hover text of a heatmap segment: its time range, confidence and text.
+fn heatmap_label(sub: &Subtitle) -> String {
+    let confidence = sub.confidence.map_or("?".to_string(), |c| format!("{:.0}%", c * 100.0));
+    format!(
+        "{} --> {} ({}): {}",
+        cs_to_srt_time(sub.start_time_cs),
+        cs_to_srt_time(sub.end_time_cs),
+        confidence,
+        sub.text.trim()
+    )
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write `subs` to `path` with `writer`, in the `--encoding` byte encoding.
+fn write_with(
+    writer: &dyn SubtitleWriter,
+    subs: &[Subtitle],
+    path: &Path,
+    encoding: TextEncoding,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    writer.write(subs, &mut buffer)?;
+    fs::write(path, encode_text(&String::from_utf8_lossy(&buffer), encoding))
+}
+
+/// Format subtitles like whisper.cpp's `--output-tsv`: a header line, then
+/// millisecond start and end times and the text, separated by tabs.
+fn subtitles_to_tsv(subtitles: &[Subtitle]) -> String {
+    let mut tsv = String::from("start\tend\ttext\n");
+    for sub in subtitles {
+        // A tab or newline inside the text would break the row
+        let text = sub.text.trim().replace(['\t', '\n', '\r'], " ");
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\n",
+            sub.start_time_cs * 10,
+            sub.end_time_cs * 10,
+            text
+        ));
+    }
+    tsv
+}
+
+/// Byte encoding for the SRT and text outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum TextEncoding {
+    /// UTF-8 without a byte order mark
+    Utf8,
+    /// UTF-8 with a byte order mark, for players that otherwise assume a legacy code page
+    Utf8Bom,
+    /// UTF-16 little endian with a byte order mark
+    Utf16le,
+}
+
+/// When to mark SRT lines as right-to-left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum RtlMode {
+    /// Only for right-to-left languages, whether forced with --language or detected
+    Auto,
+    /// For every transcript
+    Always,
+    /// Never
+    Never,
+}
+
+/// Languages whisper knows that are written right to left.
+const RTL_LANGUAGES: &[&str] = &["ar", "fa", "he", "ps", "sd", "ur", "yi"];
+
+fn uses_rtl_marks(mode: RtlMode, lang: &str) -> bool {
+    match mode {
+        RtlMode::Auto => RTL_LANGUAGES.contains(&lang),
+        RtlMode::Always => true,
+        RtlMode::Never => false,
+    }
+}
+
+/// Font colours for `--color-speakers`, bright enough to read over video and
+/// leaving out white, which players already use for unstyled text.
+const SPEAKER_COLOURS: [&str; 8] = [
+    "#ffff00", "#00ffff", "#7cfc00", "#ff80ff", "#ffa500", "#87cefa", "#ff6b6b", "#d8bfd8",
+];
+
+/// Wrap each cue's text in a `<font color>` tag for its speaker. Speakers get
+/// colours by their position in `speakers`, so a channel has the same colour
+/// in every file whoever speaks first; other cues are left as they are.
+fn colour_speakers(subs: &[Subtitle], speakers: &[String]) -> Vec<Subtitle> {
+    subs.iter()
+        .map(|sub| {
+            let Some(index) = speakers
+                .iter()
+                .position(|speaker| sub.speaker.as_ref() == Some(speaker))
+            else {
+                return sub.clone();
+            };
+            Subtitle {
+                text: format!(
+                    "<font color=\"{}\">{}</font>",
+                    SPEAKER_COLOURS[index % SPEAKER_COLOURS.len()],
+                    sub.text.trim()
+                ),
+                ..sub.clone()
+            }
+        })
+        .collect()
+}
+
+/// Start every line of each cue with a right-to-left mark (U+200F). Players
+/// that take the direction from the first strong character otherwise render
+/// lines starting with digits or Latin words left to right, moving punctuation
+/// to the wrong end.
+fn mark_rtl(subs: &[Subtitle]) -> Vec<Subtitle> {
+    subs.iter()
+        .map(|sub| Subtitle {
+            text: sub
+                .text
+                .trim()
+                .lines()
+                .map(|line| format!("\u{200F}{}", line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ..sub.clone()
+        })
+        .collect()
+}
+
+/// Text written by `encode_text` in any of its encodings, without the byte
+/// order mark. `None` if it is neither UTF-16 nor valid UTF-8.
+fn decode_text(bytes: Vec<u8>) -> Option<String> {
+    let text = match bytes.strip_prefix(&[0xFF, 0xFE]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => String::from_utf8(bytes).ok()?,
+    };
+    match text.strip_prefix('\u{feff}') {
+        Some(rest) => Some(rest.to_string()),
+        None => Some(text),
+    }
+}
+
+fn encode_text(text: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::Utf8 => text.as_bytes().to_vec(),
+        TextEncoding::Utf8Bom => {
+            let mut bytes = vec![0xEF, 0xBB, 0xBF];
+            bytes.extend_from_slice(text.as_bytes());
+            bytes
+        }
+        TextEncoding::Utf16le => std::iter::once(0xFEFF)
+            .chain(text.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+    }
+}
+
+/// Frame index of a centisecond timestamp at `fps`, rounded to the nearest frame.
+fn cs_to_frame(cs: u64, fps: f32) -> u64 {
+    (cs as f64 / 100.0 * fps as f64).round() as u64
+}
+
+/// Write one CSV row per subtitle. With `fps`, `start_frame` and `end_frame`
+/// columns are added for editors that cut on frames.
+fn write_csv_transcript(
+    subtitles: &[Subtitle],
+    out: &mut dyn Write,
+    fps: Option<f32>,
+) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    let mut header = vec!["seq", "start_srt", "end_srt", "start_cs", "end_cs"];
+    if fps.is_some() {
+        header.extend(["start_frame", "end_frame"]);
+    }
+    header.push("text");
+    writer.write_record(&header)?;
+    for sub in subtitles {
+        let mut record = vec![
+            sub.seq.to_string(),
+            cs_to_srt_time(sub.start_time_cs),
+            cs_to_srt_time(sub.end_time_cs),
+            sub.start_time_cs.to_string(),
+            sub.end_time_cs.to_string(),
+        ];
+        if let Some(fps) = fps {
+            record.push(cs_to_frame(sub.start_time_cs, fps).to_string());
+            record.push(cs_to_frame(sub.end_time_cs, fps).to_string());
+        }
+        record.push(sub.text.trim().to_string());
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Facts about a finished transcription that are reported alongside its outputs.
+struct TranscriptionReport {
+    /// Language whisper detected for most chunks, when auto-detection was used
+    language: Option<&'static str>,
+    /// Length of the transcribed audio in seconds
+    duration_secs: f64,
+    /// Wall-clock time spent transcribing, excluding model loading
+    wall_secs: f64,
+    /// Number of subtitles written
+    segment_count: usize,
+    /// End of the transcript, for `--prompt-from-previous`
+    tail: String,
+}
+
+/// The last `max_chars` characters of the transcript, starting at a word boundary.
+fn transcript_tail(subs: &[Subtitle], max_chars: usize) -> String {
+    let text = subs
+        .iter()
+        .map(|sub| sub.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let char_count = text.chars().count();
+    if char_count <= max_chars {
+        return text;
+    }
+    let tail: String = text.chars().skip(char_count - max_chars).collect();
+    // Drop the partial word the cut landed in
+    match tail.split_once(' ') {
+        Some((_, rest)) => rest.to_string(),
+        None => tail,
+    }
+}
+
+/// Pick the language detected for the most chunks.
+fn most_common_language(chunk_languages: &[&'static str]) -> Option<&'static str> {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    for lang in chunk_languages {
+        match counts.iter_mut().find(|(l, _)| l == lang) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((lang, 1)),
+        }
+    }
+    // max_by_key returns the last maximum, so reverse to prefer the earliest language on ties
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(lang, _)| lang)
+}
+
+/// Terms listed in a `--vocab` file, one per line. Blank lines and lines
+/// starting with `#` are skipped.
+fn parse_vocab(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Respell words that match a single-word term ignoring case, or that are one
+/// edit away from it. Words shorter than six characters are only matched
+/// ignoring case, since an edit turns too many short words into others.
+fn correct_vocab(text: &str, terms: &[String]) -> String {
+    let terms: Vec<&String> = terms.iter().filter(|term| !term.contains(' ')).collect();
+    text.split(' ')
+        .map(|word| {
+            let core = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if core.is_empty() || terms.iter().any(|term| term.as_str() == core) {
+                return word.to_string();
+            }
+            let lower = core.to_lowercase();
+            let replacement = terms.iter().find(|term| {
+                let term = term.to_lowercase();
+                term == lower || (core.chars().count() >= 6 && edit_distance(&term, &lower) == 1)
+            });
+            match replacement {
+                Some(term) => word.replacen(core, term, 1),
+                None => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Remove `[...]` and `(...)` annotations such as `[Music]` or `(applause)` and
+/// collapse the whitespace left behind.
+fn strip_bracketed(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let (mut square, mut round) = (0usize, 0usize);
+    for c in text.chars() {
+        match c {
+            '[' => square += 1,
+            ']' if square > 0 => square -= 1,
+            '(' => round += 1,
+            ')' if round > 0 => round -= 1,
+            _ if square == 0 && round == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Fillers removed by `--strip-fillers` when no `--filler-file` is given, in
+/// that file's format. A filler written between commas is also an ordinary
+/// word, so it is only removed where punctuation or the cue's edges set it off.
+const DEFAULT_FILLERS: &str = "um\numm\nuh\nuhm\nerm\nhmm\nmm\n,you know,\n,like,\n,I mean,\n";
+
+/// Whether `word` ends a clause: it is followed by a comma or sentence end.
+fn ends_clause(word: &str) -> bool {
+    word.ends_with([',', '.', ';', ':', '!', '?'])
+}
+
+/// Remove the `fillers` (see `DEFAULT_FILLERS`) from `text`, ignoring case, and
+/// collapse a word repeated without punctuation between, as in "the the".
+/// Sentence-ending punctuation on a removed filler moves to the word before it,
+/// and a sentence that started with one is capitalized again.
+fn strip_fillers(text: &str, fillers: &[String]) -> String {
+    let core = |word: &str| {
+        word.trim_matches(|c: char| !c.is_alphanumeric() && c != '\'')
+            .to_lowercase()
+    };
+    let suffix = |word: &str| {
+        let end = word.trim_end_matches(|c: char| !c.is_alphanumeric()).len();
+        word[end..].to_string()
+    };
+    let phrases: Vec<(Vec<String>, bool)> = fillers
+        .iter()
+        .map(|filler| {
+            let standalone = filler.len() > 1 && filler.starts_with(',') && filler.ends_with(',');
+            let words: Vec<String> =
+                filler.trim_matches(',').split_whitespace().map(core).collect();
+            (words, standalone)
+        })
+        .filter(|(words, _)| !words.is_empty())
+        .collect();
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut kept: Vec<String> = Vec::new();
+    let mut capitalize = false;
+    let mut i = 0;
+    while i < words.len() {
+        let filler_end = phrases.iter().find_map(|(phrase, standalone)| {
+            let end = i + phrase.len();
+            if end > words.len()
+                || !words[i..end].iter().map(|word| core(word)).eq(phrase.iter().cloned())
+                || words[i..end - 1].iter().any(|word| ends_clause(word))
+            {
+                return None;
+            }
+            let set_off = (i == 0 || ends_clause(words[i - 1]))
+                && (end == words.len() || ends_clause(words[end - 1]));
+            (set_off || !standalone).then_some(end)
+        });
+        if let Some(end) = filler_end {
+            let sentence_start = kept.last().is_none_or(|word| word.ends_with(['.', '!', '?']));
+            capitalize |= sentence_start && words[i].starts_with(char::is_uppercase);
+            let ending = suffix(words[end - 1]);
+            if ending.contains(['.', '!', '?'])
+                && let Some(last) = kept.last_mut()
+                && !last.ends_with(['.', '!', '?'])
+            {
+                let trimmed = last.trim_end_matches([',', ';', ':']).len();
+                last.truncate(trimmed);
+                last.push_str(&ending);
+            }
+            i = end;
+            continue;
+        }
+
+        let word = words[i];
+        if let Some(last) = kept.last_mut()
+            && !ends_clause(last)
+            && !core(word).is_empty()
+            && core(last) == core(word)
+        {
+            last.push_str(&suffix(word));
+        } else {
+            let mut chars = word.chars();
+            let word = match chars.next() {
+                Some(first) if capitalize => first.to_uppercase().chain(chars).collect(),
+                _ => word.to_string(),
+            };
+            capitalize = false;
+            kept.push(word);
+        }
+        i += 1;
+    }
+    kept.join(" ")
+}
+
+/// `subs` with `strip_fillers` applied to their text, leaving out cues that
+/// held nothing but fillers.
+fn strip_fillers_from_subtitles(subs: &[Subtitle], fillers: &[String]) -> Vec<Subtitle> {
+    let mut stripped: Vec<Subtitle> = subs
+        .iter()
+        .map(|sub| Subtitle {
+            text: strip_fillers(&sub.text, fillers),
+            ..sub.clone()
+        })
+        .collect();
+    drop_empty_subtitles(&mut stripped);
+    stripped
+}
+
+/// Remove whisper special tokens such as `<|endoftext|>` or `<|1.20|>` that can
+/// end up in segment text, leaving the surrounding whitespace as it was.
+fn strip_special_tokens(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("<|") {
+        match rest[start..].find("|>") {
+            Some(len) => {
+                result.push_str(&rest[..start]);
+                rest = &rest[start + len + 2..];
+            }
+            None => break,
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Logits filter that makes every token id in the `Vec<c_int>` behind
+/// `user_data` impossible to sample.
+unsafe extern "C" fn suppress_tokens_filter(
+    _ctx: *mut whisper_rs_sys::whisper_context,
+    _state: *mut whisper_rs_sys::whisper_state,
+    _tokens: *const whisper_rs_sys::whisper_token_data,
+    _n_tokens: c_int,
+    logits: *mut f32,
+    user_data: *mut c_void,
+) {
+    // SAFETY: user_data points at a Vec that outlives the whisper_full call and only
+    // holds ids already checked to be below n_vocab, the length of `logits`
+    let tokens = unsafe { &*(user_data as *const Vec<c_int>) };
+    for &token in tokens {
+        unsafe { *logits.add(token as usize) = f32::NEG_INFINITY };
+    }
+}
+
+/// Apply `--offset` and then `--timestamp-base`, the shifts between whisper's
+/// times and the ones written.
+fn shift_to_output_time(subtitles: &mut [Subtitle], args: &Args) {
+    if let Some(offset_ms) = args.text.offset {
+        offset_subtitles(subtitles, offset_ms / 10);
+    }
+    if let Some(base_cs) = args.text.timestamp_base {
+        offset_subtitles(subtitles, base_cs as i64);
+    }
+}
+
+/// Clean up and reshape the collected subtitles before they are written.
+fn postprocess_subtitles(mut subtitles: Vec<Subtitle>, args: &Args) -> Vec<Subtitle> {
+    if !args.text.print_special {
+        for sub in &mut subtitles {
+            sub.text = strip_special_tokens(&sub.text);
+        }
+    }
+    if args.text.strip_brackets {
+        for sub in &mut subtitles {
+            sub.text = strip_bracketed(&sub.text);
+        }
+    }
+    if args.text.vocab_correct {
+        for sub in &mut subtitles {
+            sub.text = correct_vocab(&sub.text, &args.text.vocab_terms);
+        }
+    }
+    drop_empty_subtitles(&mut subtitles);
+    if args.text.resegment_sentences {
+        subtitles = resegment_sentences(&subtitles);
+    }
+    if let Some(max_secs) = args.text.max_segment_length {
+        subtitles = split_long_subtitles(subtitles, (max_secs * 100.0).round() as u64);
+    }
+    if let Some(min_cs) = args.text.min_cue_duration {
+        subtitles = merge_short_subtitles(subtitles, min_cs);
+    }
+    shift_to_output_time(&mut subtitles, args);
+    // Last, so the tag isn't mistaken for part of a sentence by the steps above
+    if args.text.tag_language {
+        for sub in &mut subtitles {
+            if let Some(lang) = &sub.lang {
+                sub.text = format!("[{}] {}", lang, sub.text.trim());
+            }
+        }
+    }
+    subtitles
+}
+
+/// Write every output file selected by `args` for one set of subtitles,
+/// recording any split SRT parts in `outputs.srt_parts`.
+fn write_outputs(
+    subtitles: &[Subtitle],
+    outputs: &mut OutputPaths,
+    args: &Args,
+    lang: &str,
+) -> Result<(), Box<dyn Error>> {
+    let cleaned;
+    let raw_subtitles = subtitles;
+    let subtitles = if args.text.strip_fillers {
+        cleaned = strip_fillers_from_subtitles(subtitles, &args.text.fillers);
+        &cleaned
+    } else {
+        subtitles
+    };
+    for format in output_formats(args) {
+        let writer = format.writer(args);
+        let failed = |e: io::Error| format!("Failed to write {} output: {}", format.label(), e);
+        if format == OutputFormat::Raw {
+            write_with(&*writer, raw_subtitles, &outputs.path(format, &*writer), args.out.encoding)
+                .map_err(failed)?;
+            continue;
+        }
+        if format != OutputFormat::Srt {
+            write_with(&*writer, subtitles, &outputs.path(format, &*writer), args.out.encoding)
+                .map_err(failed)?;
+            continue;
+        }
+
+        let marked;
+        let srt_subtitles = if uses_rtl_marks(args.out.rtl, lang) {
+            marked = mark_rtl(subtitles);
+            &marked
+        } else {
+            subtitles
+        };
+        // Write subtitles to SRT file, or to one file per --split-output window
+        match args.out.split_output {
+            Some(minutes) => {
+                let part_cs = (minutes * 6000.0).round() as u64;
+                let parts =
+                    split_subtitles_by_time(srt_subtitles, part_cs, !args.out.split_absolute);
+                outputs.srt_parts.clear();
+                for (i, part) in parts.iter().enumerate() {
+                    let path = outputs.srt_part(i + 1);
+                    write_with(&*writer, part, &path, args.out.encoding).map_err(failed)?;
+                    outputs.srt_parts.push(path);
+                }
+            }
+            None => {
+                write_with(&*writer, srt_subtitles, &outputs.srt, args.out.encoding)
+                    .map_err(failed)?;
+            }
+        }
+        if args.out.validate_output {
+            let written = if outputs.srt_parts.is_empty() {
+                std::slice::from_ref(&outputs.srt)
+            } else {
+                &outputs.srt_parts[..]
+            };
+            for path in written {
+                // Speakers' cues are merged by start time and overlap when they talk at once
+                let allow_overlaps = !args.per_channel_speakers.is_empty();
+                validate_srt(path, allow_overlaps).map_err(|problems| {
+                    format!("{} is malformed:\n  {}", path.display(), problems.join("\n  "))
+                })?;
+            }
+        }
+    }
+    if let Some(master) = &args.out.append_to {
+        let count = append_to_master(master, subtitles, args.out.crlf, args.out.encoding)
+            .map_err(|e| format!("Failed to append to {}: {}", master.display(), e))?;
+        status!("Appended {} cue(s) to {}.", count, master.display());
+    }
+    Ok(())
+}
+
+/// `subs` renumbered to follow the last cue of `existing` and shifted to start
+/// where it ends, so a new recording continues a running transcript.
+fn continue_subtitles(existing: &[Subtitle], subs: &[Subtitle]) -> Vec<Subtitle> {
+    let last_seq = existing.iter().map(|sub| sub.seq).max().unwrap_or(0);
+    let last_end_cs = existing.iter().map(|sub| sub.end_time_cs).max().unwrap_or(0);
+    let mut continued = subs.to_vec();
+    offset_subtitles(&mut continued, last_end_cs as i64);
+    for (i, sub) in continued.iter_mut().enumerate() {
+        sub.seq = last_seq + i as u32 + 1;
+    }
+    continued
+}
+
+/// Append `subs` to the SRT at `master` (created if missing) with
+/// `continue_subtitles`, returning how many cues were added.
+fn append_to_master(
+    master: &Path,
+    subs: &[Subtitle],
+    crlf: bool,
+    encoding: TextEncoding,
+) -> io::Result<usize> {
+    let content = match fs::read(master) {
+        Ok(bytes) => decode_text(bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not valid UTF-8"))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let existing = parse_srt(&content)?;
+    // Cues must be separated by a blank line even if the master lacks a trailing one
+    let newline = if crlf { "\r\n" } else { "\n" };
+    let mut updated = content.trim_end_matches(['\r', '\n']).to_string();
+    if !updated.is_empty() {
+        updated.push_str(&newline.repeat(2));
+    }
+    updated.push_str(&subtitles_to_srt(&continue_subtitles(&existing, subs), crlf));
+    fs::write(master, encode_text(&updated, encoding))?;
+    Ok(subs.len())
+}
+
+/// Load the whisper model once so it can be shared by every input.
+fn load_model(whisper_path: &Path, args: &Args) -> Result<WhisperContext, Box<dyn Error>> {
+    // whisper.cpp takes a C string, so refuse paths that would be mangled by a lossy conversion
+    let whisper_path_str = whisper_path
+        .to_str()
+        .ok_or_else(|| format!("Model path {} is not valid UTF-8", whisper_path.display()))?;
+    let backend = if args.model.cpu_only { "CPU" } else { compiled_backend() };
+    status!("Whisper backend: {}", backend);
+    if args.model.gpu_device.is_some() && compiled_backend() == "CPU" {
+        eprintln!(
+            "Warning: --gpu-device has no effect because this build has no GPU backend; \
+             rebuild with --features cuda, metal, vulkan or hipblas"
+        );
+    }
+    let ctx = WhisperContext::new_with_params(
+        whisper_path_str,
+        WhisperContextParameters {
+            use_gpu: !args.model.cpu_only,
+            flash_attn: args.model.fa,
+            gpu_device: args.model.gpu_device.unwrap_or(0),
+            ..Default::default()
+        },
+    )?;
+    Ok(ctx)
+}
+
+/// The loaded model and, once the GPU has run out of memory with
+/// `--gpu-fallback-cpu`, its CPU copy. The copy is kept so that later inputs
+/// of a batch, or later requests to `serve`, don't load it again.
+struct Model {
+    ctx: WhisperContext,
+    cpu_fallback: std::cell::OnceCell<WhisperContext>,
+}
+
+impl Model {
+    fn new(ctx: WhisperContext) -> Self {
+        Model {
+            ctx,
+            cpu_fallback: std::cell::OnceCell::new(),
+        }
+    }
+
+    /// The context to decode with: the CPU copy once there is one.
+    fn active(&self) -> &WhisperContext {
+        self.cpu_fallback.get().unwrap_or(&self.ctx)
+    }
+
+    fn on_cpu(&self) -> bool {
+        self.cpu_fallback.get().is_some()
+    }
+
+    /// Switch to the CPU copy after `err`, loading it with `fall_back_to_cpu`.
+    fn fall_back_to_cpu(
+        &self,
+        err: WhisperError,
+        whisper_path: &Path,
+        args: &Args,
+    ) -> Result<&WhisperContext, Box<dyn Error>> {
+        let cpu = fall_back_to_cpu(err, whisper_path, args)?;
+        Ok(self.cpu_fallback.get_or_init(|| cpu))
+    }
+
+    /// A fresh state on the CPU copy to retry a `call` that failed with `err`
+    /// on the GPU; any other error, or one already on the CPU, is passed on.
+    fn cpu_state_after(
+        &self,
+        err: WhisperError,
+        call: WhisperCall,
+        whisper_path: &Path,
+        args: &Args,
+    ) -> Result<WhisperState, Box<dyn Error>> {
+        if self.on_cpu() || !is_gpu_failure(&err, call, args) {
+            return Err(io::Error::other(err).into());
+        }
+        Ok(self.fall_back_to_cpu(err, whisper_path, args)?.create_state()?)
+    }
+}
+
+/// The whisper calls whose errors are checked for a failed allocation; each
+/// numbers its failures differently.
+#[derive(Clone, Copy)]
+enum WhisperCall {
+    CreateState,
+    Full,
+    LangDetect,
+}
+
+/// Whether `err` from `call` is how whisper.cpp reports a failed GPU allocation
+/// while the model runs on the GPU.
+fn is_gpu_failure(err: &WhisperError, call: WhisperCall, args: &Args) -> bool {
+    compiled_backend() != "CPU" && !args.model.cpu_only && is_allocation_failure(err, call)
+}
+
+/// Whether `err` from `call` can come from running out of memory. whisper.cpp
+/// has no dedicated error: a state whose buffers can't be allocated is never
+/// created, `whisper_full` returns -6 when the encoder fails, -7 when it can't
+/// grow the KV cache and -8 when the decoder fails, and language detection
+/// returns -6 when the encoder fails and -7 when the decoder does.
+fn is_allocation_failure(err: &WhisperError, call: WhisperCall) -> bool {
+    match call {
+        WhisperCall::CreateState => matches!(err, WhisperError::FailedToCreateState),
+        WhisperCall::Full => matches!(err, WhisperError::GenericError(-8..=-6)),
+        WhisperCall::LangDetect => matches!(err, WhisperError::GenericError(-7..=-6)),
+    }
+}
+
+/// Load the model again on the CPU after the GPU ran out of memory, or explain
+/// the failure when `--gpu-fallback-cpu` isn't set.
+fn fall_back_to_cpu(
+    err: WhisperError,
+    whisper_path: &Path,
+    args: &Args,
+) -> Result<WhisperContext, Box<dyn Error>> {
+    if !args.model.gpu_fallback_cpu {
+        return Err(format!(
+            "GPU out of memory ({}) - try a smaller model, --cpu-only or --gpu-fallback-cpu",
+            err
+        )
+        .into());
+    }
+    let whisper_path_str = whisper_path
+        .to_str()
+        .ok_or_else(|| format!("Model path {} is not valid UTF-8", whisper_path.display()))?;
+    let ctx = WhisperContext::new_with_params(
+        whisper_path_str,
+        WhisperContextParameters {
+            use_gpu: false,
+            flash_attn: args.model.fa,
+            ..Default::default()
+        },
+    )?;
+    Ok(ctx)
+}
+
+/// The whisper backend this binary was compiled with.
+fn compiled_backend() -> &'static str {
+    if cfg!(feature = "cuda") {
+        "CUDA"
+    } else if cfg!(feature = "metal") {
+        "Metal"
+    } else if cfg!(feature = "vulkan") {
+        "Vulkan"
+    } else if cfg!(feature = "hipblas") {
+        "HIPBLAS"
+    } else {
+        "CPU"
+    }
+}
+
+/// Decoding parameters taken from the command line, shared by file and microphone input.
+fn base_params(args: &Args) -> FullParams<'_, '_> {
+    let mut params = FullParams::new(SamplingStrategy::default());
+    params.set_initial_prompt(&initial_prompt(args));
+    params.set_language(Some(&args.whisper.language));
+    // Without context each chunk is decoded independently, so a hallucination in
+    // one chunk cannot carry over into the next
+    params.set_no_context(args.whisper.no_context);
+    params.set_single_segment(args.whisper.single_segment);
+    if args.out.karaoke {
+        params.set_token_timestamps(true);
+    }
+    if let Some(max_len) = args.whisper.max_len {
+        // whisper only honours max_len when it has per-token timestamps to split on
+        params.set_token_timestamps(true);
+        params.set_max_len(max_len);
+    }
+    if let Some(max_tokens) = args.whisper.max_tokens {
+        params.set_max_tokens(max_tokens);
+    }
+    if let Some(suppress_blank) = args.whisper.suppress_blank {
+        params.set_suppress_blank(suppress_blank);
+    }
+    // whisper drops a segment it thinks is silence but still decoded with low confidence,
+    // which is where it tends to invent text during quiet passages
+    params.set_no_speech_thold(args.whisper.no_speech_threshold);
+    params.set_logprob_thold(args.whisper.logprob_threshold);
+    // Low entropy means a repetition loop such as "the the the"; whisper then re-decodes
+    // the chunk at a higher temperature
+    params.set_entropy_thold(args.whisper.entropy_threshold);
+    // Keeps special tokens such as <|endoftext|> in the segment text for debugging
+    params.set_print_special(args.text.print_special);
+    // A smaller audio context shrinks the encoder's buffers; 0 keeps the model's default
+    if let Some(audio_ctx) = args.whisper.audio_ctx {
+        params.set_audio_ctx(audio_ctx);
+    }
+    if let Some(max_text_ctx) = args.whisper.max_text_ctx {
+        params.set_n_max_text_ctx(max_text_ctx);
+    }
+    if args.no_timestamps {
+        // Segments still get coarse chunk-level times, but no timestamp tokens are decoded
+        params.set_no_timestamps(true);
+        params.set_print_timestamps(false);
+    }
+    params
+}
+
+/// Samples per encoder position: each covers two 10ms mel frames.
+const SAMPLES_PER_AUDIO_CTX: usize = 2 * SAMPLE_RATE / 100;
+
+/// Samples per chunk. The encoder only sees the first `--audio-ctx` positions
+/// of a window, so a reduced context needs shorter chunks or audio is skipped.
+fn chunk_size(args: &Args) -> usize {
+    match args.whisper.audio_ctx {
+        Some(audio_ctx) if audio_ctx > 0 => {
+            CHUNK_SIZE.min(audio_ctx as usize * SAMPLES_PER_AUDIO_CTX)
+        }
+        _ => CHUNK_SIZE,
+    }
+}
+
+/// Short sample sentences in a language's usual punctuation and spacing. whisper
+/// copies the style of its prompt, so these stop it from, say, writing Japanese
+/// with ASCII commas or leaving out punctuation altogether.
+const DEFAULT_PROMPTS: &[(&str, &str)] = &[
+    ("ja", "こんにちは。今日は、いい天気ですね。"),
+    ("zh", "你好。今天的天气很好，我们出去走走吧。"),
+    ("yue", "你好。今日天氣好好，我哋出去行下啦。"),
+    ("ko", "안녕하세요. 오늘은 날씨가 좋네요."),
+    ("th", "สวัสดีครับ วันนี้อากาศดีมาก"),
+    ("ar", "مرحبا، كيف حالك؟ الطقس جميل اليوم."),
+    ("fr", "Bonjour, comment allez-vous ? Il fait beau aujourd'hui !"),
+    ("de", "Guten Tag, wie geht es Ihnen? Heute ist schönes Wetter."),
+    ("es", "Hola, ¿qué tal? ¡Hoy hace muy buen tiempo!"),
+];
+
+/// Built-in prompt for `language`, used when `--prompt` isn't given.
+fn default_prompt(language: &str) -> Option<&'static str> {
+    DEFAULT_PROMPTS.iter().find(|(lang, _)| *lang == language).map(|(_, prompt)| *prompt)
+}
+
+/// The initial prompt: `--prompt` (or the language's default prompt), the `--vocab` terms and, with
+/// `--prompt-from-previous`, the end of the previous transcript. whisper keeps
+/// the end of an overlong prompt, so the most specific context goes last.
+fn initial_prompt(args: &Args) -> String {
+    let mut parts = Vec::new();
+    if let Some(prompt) = &args.whisper.prompt {
+        parts.push(prompt.clone());
+    } else if !args.whisper.no_default_prompt
+        && let Some(prompt) = default_prompt(&args.whisper.language)
+    {
+        parts.push(prompt.to_string());
+    }
+    if !args.text.vocab_terms.is_empty() {
+        parts.push(args.text.vocab_terms.join(", "));
+    }
+    if let Some(previous) = &args.previous_transcript {
+        parts.push(previous.clone());
+    }
+    if parts.is_empty() {
+        return "experience".to_string();
+    }
+    parts.join(" ")
+}
+
+/// Turn the segments of the last `full` run into subtitles, shifting their
+/// timestamps by `offset_cs` and numbering them from `first_seq`. With
+/// `word_times`, each subtitle also gets the times of its words, which needs
+/// token timestamps to have been enabled for the run. Every subtitle gets the
+/// mean probability of its text tokens as its confidence.
+fn collect_segments(
+    state: &WhisperState,
+    offset_cs: i64,
+    first_seq: u32,
+    word_times: bool,
+) -> Result<Vec<Subtitle>, Box<dyn Error>> {
+    let num_segments = state.full_n_segments()?;
+    let mut subtitles = Vec::with_capacity(num_segments.max(0) as usize);
+    for i in 0..num_segments {
+        let bytes = state.full_get_segment_bytes(i)?;
+        let segment = String::from_utf8_lossy(&bytes).to_string();
+        let start_timestamp_cs = state.full_get_segment_t0(i)? + offset_cs;
+        let end_timestamp_cs = state.full_get_segment_t1(i)? + offset_cs;
+        let mut tokens = Vec::new();
+        let mut probabilities = Vec::new();
+        for token in 0..state.full_n_tokens(i)? {
+            let data = state.full_get_token_data(i, token)?;
+            let bytes = state.full_get_token_bytes(i, token)?.to_vec();
+            if !is_special_token(&bytes) {
+                probabilities.push(data.p);
+            }
+            if word_times {
+                tokens.push((bytes, data.t0 + offset_cs, data.t1 + offset_cs));
+            }
+        }
+        let confidence = (!probabilities.is_empty())
+            .then(|| probabilities.iter().sum::<f32>() / probabilities.len() as f32);
+
+        subtitles.push(Subtitle {
+            seq: first_seq + i as u32,
+            start_time_cs: start_timestamp_cs as u64,
+            end_time_cs: end_timestamp_cs as u64,
+            text: segment,
+            words: tokens_to_words(&tokens),
+            lang: None,
+            confidence,
+            speaker: None,
+        });
+    }
+    Ok(subtitles)
+}
+
+/// Control tokens such as `[_BEG_]` or `<|endoftext|>`, which aren't part of the text.
+fn is_special_token(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"[_") || bytes.starts_with(b"<|")
+}
+
+/// Group a segment's `(bytes, t0, t1)` tokens into words: a token starting with
+/// a space begins a new word. Special tokens such as `[_BEG_]` are skipped, and
+/// bytes are joined before decoding since a character may span two tokens.
+fn tokens_to_words(tokens: &[(Vec<u8>, i64, i64)]) -> Vec<Word> {
+    let mut words: Vec<(Vec<u8>, i64, i64)> = Vec::new();
+    for (bytes, t0, t1) in tokens {
+        if is_special_token(bytes) {
+            continue;
+        }
+        match words.last_mut() {
+            Some((word, _, end)) if !bytes.starts_with(b" ") => {
+                word.extend_from_slice(bytes);
+                *end = (*t1).max(*end);
+            }
+            _ => words.push((bytes.clone(), *t0, *t1)),
+        }
+    }
+    words
+        .into_iter()
+        .map(|(bytes, t0, t1)| Word {
+            start_time_cs: t0.max(0) as u64,
+            end_time_cs: t1.max(t0).max(0) as u64,
+            text: String::from_utf8_lossy(&bytes).trim().to_string(),
+        })
+        .filter(|word| !word.text.is_empty())
+        .collect()
+}
+
+/// Start of each chunk in centiseconds, for chunks following each other from
+/// sample `first_sample`. Each one is derived from the samples before it rather
+/// than by adding up rounded chunk lengths, so rounding never accumulates and
+/// a short last chunk advances only by what it holds.
+fn chunk_offsets_cs(first_sample: usize, chunks: &[&[f32]]) -> Vec<i64> {
+    let mut processed_samples = first_sample;
+    chunks
+        .iter()
+        .map(|chunk| {
+            let offset = (processed_samples as f64 / SAMPLE_RATE as f64 * 100.0).round() as i64;
+            processed_samples += chunk.len();
+            offset
+        })
+        .collect()
+}
+
+/// Transcribe `samples` chunk by chunk. With `live_srt`, each chunk's cues are
+/// appended to that file as soon as they are decoded, before any
+/// post-processing; the caller's final write replaces it.
+fn handle_transcription(
+    model: &Model,
+    whisper_path: &Path,
+    samples: &[f32],
+    chunk_size: usize,
+    args: &Args,
+    live_srt: Option<&Path>,
+) -> Result<(Vec<Subtitle>, TranscriptionReport), Box<dyn Error>> {
+    let started = Instant::now();
+    let audio_secs = samples.len() as f64 / SAMPLE_RATE as f64;
+
+    // Chunks are decoded in order on this one state: whisper keeps the previous
+    // chunk's text in it and uses it as context for the next, so chunks must not
+    // be split across states or threads unless `--no-context` is set
+    let ctx = model.active();
+    let mut state = match ctx.create_state() {
+        Ok(state) => state,
+        Err(e) => {
+            let state = model.cpu_state_after(e, WhisperCall::CreateState, whisper_path, args)?;
+            eprintln!("GPU out of memory creating the decoder state; continuing on the CPU");
+            state
+        }
+    };
+    // Params are built once per input; whisper re-applies the initial prompt in
+    // front of the carried-over context on every chunk
+    let mut params = base_params(args);
+    let auto_detect = args.whisper.language == "auto";
+    let lang_candidates = lang_candidate_ids(args);
+
+    // Must stay alive until the last `state.full` call since whisper reads it through user_data
+    let mut suppressed_tokens: Vec<c_int> = Vec::new();
+    for &token in &args.whisper.suppress_tokens {
+        if (0..ctx.n_vocab()).contains(&token) {
+            suppressed_tokens.push(token);
+        } else {
+            eprintln!(
+                "Ignoring token id {} outside the model vocabulary (0..{})",
+                token,
+                ctx.n_vocab()
+            );
+        }
+    }
+    if !suppressed_tokens.is_empty() {
+        // SAFETY: the filter only reads `suppressed_tokens`, which outlives `params`' use below
+        unsafe {
+            params.set_filter_logits_callback(Some(suppress_tokens_filter));
+            params.set_filter_logits_callback_user_data(
+                &mut suppressed_tokens as *mut Vec<c_int> as *mut c_void,
+            );
+        }
+    }
+
+    // Timestamps start at the trimmed lead-in, so they still match the original audio
+    let speech = if args.input.trim_silence {
+        let speech = speech_bounds(samples, SILENCE_THRESHOLD_DB, SILENCE_PADDING);
+        status!(
+            "Trimmed {:.1}s of leading and {:.1}s of trailing silence",
+            speech.start as f64 / SAMPLE_RATE as f64,
+            (samples.len() - speech.end) as f64 / SAMPLE_RATE as f64
+        );
+        speech
+    } else {
+        0..samples.len()
+    };
+    let sample_batches = samples[speech.clone()].chunks(chunk_size).collect::<Vec<_>>();
+    let chunk_count = sample_batches.len();
+
+    let mut pb = ChunkProgress::new(args.progress, chunk_count);
+    if let Some(callback) = pb.whisper_callback() {
+        params.set_progress_callback_safe(callback);
+    }
+
+    // Any byte order mark goes first; each chunk is then written without one
+    let bom = encode_text("", args.out.encoding);
+    let mut live_srt = match live_srt {
+        Some(path) => {
+            let mut file = fs::File::create(path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            file.write_all(&bom)?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    let mut subtitles = Vec::new();
+    let mut seq_number = 1;
+    let chunk_offsets = chunk_offsets_cs(speech.start, &sample_batches);
+    let mut chunk_languages = Vec::new();
+
+    for (chunk_index, samples) in sample_batches.into_iter().enumerate() {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            pb.println(format!(
+                "Interrupted: {} of {} chunks completed, writing partial transcript",
+                chunk_index, chunk_count
+            ));
+            break;
+        }
+
+        // Detect among the candidates ourselves and force the winner, since
+        // whisper's own detection always picks the global argmax
+        let mut candidate_lang = None;
+        if !lang_candidates.is_empty() {
+            let probs = match lang_probabilities(&mut state, samples) {
+                Ok((_, probs)) => probs,
+                Err(e) => {
+                    // Unlike `full`, detection leaves the state for us to free
+                    state = model.cpu_state_after(e, WhisperCall::LangDetect, whisper_path, args)?;
+                    pb.println(format!(
+                        "GPU out of memory on chunk {}/{}; continuing on the CPU",
+                        chunk_index + 1,
+                        chunk_count
+                    ));
+                    lang_probabilities(&mut state, samples).map_err(io::Error::other)?.1
+                }
+            };
+            candidate_lang = most_likely_candidate(&probs, &lang_candidates);
+            params.set_language(candidate_lang.and_then(whisper_rs::get_lang_str));
+        }
+
+        // `full` takes the params by value; the clone is a shallow copy whose string
+        // and callback pointers still refer to the buffers owned by `params`
+        if let Err(e) = state.full(params.clone(), samples) {
+            if matches!(e, WhisperError::GenericError(-7)) {
+                // whisper.cpp frees the state itself on this error; dropping it would free it twice
+                std::mem::forget(state);
+            }
+            // The new state starts without the previous chunks' text as context
+            state = model.cpu_state_after(e, WhisperCall::Full, whisper_path, args)?;
+            pb.println(format!(
+                "GPU out of memory on chunk {}/{}; continuing on the CPU",
+                chunk_index + 1,
+                chunk_count
+            ));
+            state
+                .full(params.clone(), samples)
+                .map_err(io::Error::other)?;
+        }
+
+        let mut chunk_lang = args.whisper.language.as_str();
+        if auto_detect {
+            let lang_id = match candidate_lang {
+                Some(lang_id) => lang_id,
+                None => state.full_lang_id_from_state()?,
+            };
+            let lang = whisper_rs::get_lang_str(lang_id).unwrap_or("unknown");
+            // Only log when detection flips, so mixed-language files show where it happened
+            if chunk_languages.last() != Some(&lang) {
+                pb.println(format!(
+                    "Chunk {}/{}: detected language '{}'",
+                    chunk_index + 1,
+                    chunk_count,
+                    lang
+                ));
+            }
+            chunk_languages.push(lang);
+            chunk_lang = lang;
+        }
+
+        let mut chunk_subtitles =
+            collect_segments(&state, chunk_offsets[chunk_index], seq_number, args.out.karaoke)?;
+        for sub in &mut chunk_subtitles {
+            sub.lang = Some(chunk_lang.to_string());
+            // Before anything is printed, so censored words never reach the terminal
+            apply_replacements(&mut sub.text, &args.text.replace);
+        }
+        if args.verbose {
+            pb.chunk_segments(chunk_index, chunk_count, &chunk_subtitles);
+        }
+        if args.stream_jsonl {
+            // Shifted like the written files, so the times match them
+            let mut streamed = chunk_subtitles.clone();
+            shift_to_output_time(&mut streamed, args);
+            for sub in &streamed {
+                println!("{}", segment_json(sub));
+            }
+        }
+        if let Some(file) = &mut live_srt {
+            let srt = subtitles_to_srt(&chunk_subtitles, args.out.crlf);
+            let srt = encode_text(&srt, args.out.encoding);
+            file.write_all(&srt[bom.len()..])?;
+            file.flush()?;
+        }
+        seq_number += chunk_subtitles.len() as u32;
+        subtitles.extend(chunk_subtitles);
+        pb.inc();
+    }
+
+    pb.finish();
+
+    // Report throughput as real-time factor (audio seconds per wall-clock second)
+    let wall_secs = started.elapsed().as_secs_f64();
+    status!(
+        "Audio duration: {:.1}s, wall time: {:.1}s, RTF: {:.2}x",
+        audio_secs,
+        wall_secs,
+        audio_secs / wall_secs.max(f64::EPSILON)
+    );
+
+    let subtitles = postprocess_subtitles(subtitles, args);
+
+    let language = most_common_language(&chunk_languages);
+    if let Some(lang) = language {
+        status!("Detected language: {}", lang);
+    }
+
+    let report = TranscriptionReport {
+        language,
+        duration_secs: audio_secs,
+        wall_secs,
+        segment_count: subtitles.len(),
+        tail: transcript_tail(&subtitles, PROMPT_TAIL_CHARS),
+    };
+    Ok((subtitles, report))
+}
+
+
+/// How chunk progress is reported while transcribing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ProgressMode {
+    /// Interactive terminal progress bar
+    Bar,
+    /// Newline-delimited JSON events on stderr, for GUI frontends
+    Json,
+}
+
+/// One `--stream-jsonl` line: the segment as whisper returned it, with times in
+/// milliseconds from the start of the audio and its chunk's language if known.
+fn segment_json(sub: &Subtitle) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "seq": sub.seq,
+        "start_ms": sub.start_time_cs * 10,
+        "end_ms": sub.end_time_cs * 10,
+        "text": sub.text.trim(),
+    });
+    if let Some(lang) = &sub.lang {
+        json["lang"] = lang.as_str().into();
+    }
+    json
+}
+
+/// Words of each chunk's text shown by `--verbose`.
+const VERBOSE_PREVIEW_WORDS: usize = 8;
+
+/// The first `max_words` words of the subtitles' text, with `...` if there are more.
+fn text_preview(subtitles: &[Subtitle], max_words: usize) -> String {
+    let mut words = subtitles.iter().flat_map(|sub| sub.text.split_whitespace());
+    let mut preview: Vec<&str> = words.by_ref().take(max_words).collect();
+    if words.next().is_some() {
+        preview.push("...");
+    }
+    preview.join(" ")
+}
+
+/// Bar positions per chunk, so whisper's percentage within a chunk can move the bar.
+const BAR_STEPS_PER_CHUNK: u64 = 100;
+
+/// Chunk progress reporter: either the indicatif bar or a stream of JSON events.
+enum ChunkProgress {
+    Bar(indicatif::ProgressBar),
+    Json {
+        total: usize,
+        done: usize,
+        started: Instant,
+    },
+}
+
+impl ChunkProgress {
+    fn new(mode: ProgressMode, total: usize) -> Self {
+        match mode {
+            ProgressMode::Bar => {
+                let pb = indicatif::ProgressBar::new(total as u64 * BAR_STEPS_PER_CHUNK);
+                pb.set_style(
+                    indicatif::ProgressStyle::default_bar()
+                        .template(
+                            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {msg} ({eta})",
+                        )
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                pb.set_message(format!("0/{}", total));
+                pb.enable_steady_tick(Duration::from_millis(100));
+                ChunkProgress::Bar(pb)
+            }
+            ProgressMode::Json => {
+                let progress = ChunkProgress::Json {
+                    total,
+                    done: 0,
+                    started: Instant::now(),
+                };
+                progress.emit(serde_json::json!({ "event": "start", "total": total }));
+                progress
+            }
+        }
+    }
+
+    fn emit(&self, event: serde_json::Value) {
+        // One event per line so wrappers can parse stderr incrementally
+        eprintln!("{}", event);
+    }
+
+    fn println(&self, message: String) {
+        match self {
+            ChunkProgress::Bar(pb) => pb.println(message),
+            ChunkProgress::Json { .. } => {
+                self.emit(serde_json::json!({ "event": "message", "text": message }))
+            }
+        }
+    }
+
+    /// Log what whisper returned for one chunk, for `--verbose`.
+    fn chunk_segments(&self, chunk_index: usize, chunk_count: usize, segments: &[Subtitle]) {
+        let preview = text_preview(segments, VERBOSE_PREVIEW_WORDS);
+        match self {
+            ChunkProgress::Bar(pb) => pb.println(format!(
+                "Chunk {}/{}: {} segment(s): {}",
+                chunk_index + 1,
+                chunk_count,
+                segments.len(),
+                preview
+            )),
+            ChunkProgress::Json { .. } => self.emit(serde_json::json!({
+                "event": "segments",
+                "chunk": chunk_index + 1,
+                "total": chunk_count,
+                "count": segments.len(),
+                "preview": preview,
+            })),
+        }
+    }
+
+    /// Callback for whisper's progress within the current chunk, which moves the
+    /// bar between chunk boundaries. JSON progress stays at one event per chunk.
+    fn whisper_callback(&self) -> Option<impl FnMut(i32) + 'static> {
+        let ChunkProgress::Bar(pb) = self else {
+            return None;
+        };
+        let pb = pb.clone();
+        Some(move |percent: i32| {
+            // Stay short of the next boundary; `inc` moves onto it once the chunk is done
+            let within = (percent.max(0) as u64).min(BAR_STEPS_PER_CHUNK - 1);
+            let chunk_start = pb.position() / BAR_STEPS_PER_CHUNK * BAR_STEPS_PER_CHUNK;
+            pb.set_position(chunk_start + within);
+        })
+    }
+
+    fn inc(&mut self) {
+        match self {
+            ChunkProgress::Bar(pb) => {
+                let done = pb.position() / BAR_STEPS_PER_CHUNK + 1;
+                pb.set_position(done * BAR_STEPS_PER_CHUNK);
+                let total = pb.length().unwrap_or(0) / BAR_STEPS_PER_CHUNK;
+                pb.set_message(format!("{}/{}", done, total));
+            }
+            ChunkProgress::Json {
+                total,
+                done,
+                started,
+            } => {
+                *done += 1;
+                let elapsed = started.elapsed().as_secs_f64();
+                let eta_secs = elapsed / *done as f64 * total.saturating_sub(*done) as f64;
+                let event = serde_json::json!({
+                    "event": "chunk",
+                    "done": *done,
+                    "total": *total,
+                    "eta_secs": eta_secs.round() as u64,
+                });
+                self.emit(event);
+            }
+        }
+    }
+
+    fn finish(&self) {
+        match self {
+            ChunkProgress::Bar(pb) => pb.finish_with_message("Done"),
+            ChunkProgress::Json { total, done, .. } => {
+                self.emit(serde_json::json!({ "event": "done", "done": done, "total": total }))
+            }
+        }
+    }
+}
+
+/// Parse an existing SRT file and write it out again through the normal
+/// post-processing and output pipeline, skipping transcription entirely.
+fn reformat_srt(srt_path: &Path, args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut subtitles = parse_srt(&fs::read_to_string(srt_path)?)?;
+    for sub in &mut subtitles {
+        apply_replacements(&mut sub.text, &args.text.replace);
+    }
+    let subtitles = postprocess_subtitles(subtitles, args);
+    if subtitles.is_empty() {
+        eprintln!("Warning: no speech was transcribed; the output files will be empty");
+    }
+
+    let model_path = Path::new(args.model.model_path.as_deref().unwrap_or_default());
+    let naming = OutputNaming::new(args, model_path, &args.whisper.language);
+    let mut outputs = OutputPaths::new(srt_path, None, &naming)?;
+    write_outputs(&subtitles, &mut outputs, args, &args.whisper.language)?;
+
+    println!("Reformatted {} cues.", subtitles.len());
+    announce_outputs(&outputs, args);
+    Ok(())
+}
+
+/// Describe an input without converting it: WAV headers are read directly,
+/// anything else is identified by extension only since ffmpeg is not run.
+fn describe_input(audio_path: &Path) -> String {
+    match WavReader::open(audio_path) {
+        Ok(reader) => {
+            let spec = reader.spec();
+            let secs = reader.duration() as f64 / spec.sample_rate as f64;
+            let chunks = (secs * SAMPLE_RATE as f64 / CHUNK_SIZE as f64).ceil();
+            format!(
+                "WAV, {} Hz, {} channel(s), {}-bit {:?}, {:.1}s -> {} chunk(s)",
+                spec.sample_rate,
+                spec.channels,
+                spec.bits_per_sample,
+                spec.sample_format,
+                secs,
+                chunks
+            )
+        }
+        Err(_) => {
+            let ext = audio_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_uppercase())
+                .unwrap_or_else(|| "unknown".to_string());
+            format!("{} (converted by ffmpeg; duration and chunk count unknown)", ext)
+        }
+    }
+}
+
+/// Print what a run with `args` would do, without running ffmpeg or whisper.
+fn print_dry_run(args: &Args, whisper_path: &Path) {
+    println!(
+        "Model: {} ({})",
+        whisper_path.display(),
+        if whisper_path.exists() {
+            "found"
+        } else if known_model_name(whisper_path).is_some() {
+            "will be downloaded"
+        } else {
+            "missing"
+        }
+    );
+    println!("Language: {}", args.whisper.language);
+
+    for audio_path_str in &args.audio_paths {
+        let audio_path = Path::new(audio_path_str);
+        println!("\nInput: {}", audio_path_str);
+        if !audio_path.exists() {
+            println!("  does not exist");
+            continue;
+        }
+        println!("  Format: {}", describe_input(audio_path));
+
+        let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+        let outputs = match OutputPaths::new(audio_path, None, &naming) {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                println!("  {}", e);
+                continue;
+            }
+        };
+        for format in output_formats(args) {
+            let path = outputs.path(format, &*format.writer(args));
+            println!("  {}: {}", format.label(), path.display());
+        }
+        if let Some(keep_path) = &args.keep_converted_wav {
+            let keep_path = keep_path.as_ref().unwrap_or(&outputs.converted_wav);
+            println!("  Converted WAV: {}", keep_path.display());
+        }
+    }
+}
+
+/// Resolve a model argument: an existing path is used as is, otherwise a known
+/// model name (e.g. `large-v3-turbo-q5_0`) or alias (e.g. `turbo`) maps to
+/// `ggml-<name>.bin` in the working directory.
+fn resolve_model(name: &str) -> PathBuf {
+    let path = PathBuf::from(name);
+    if !path.exists()
+        && let Some(canonical) = canonical_model_name(name)
+    {
+        return PathBuf::from(format!("ggml-{}.bin", canonical));
+    }
+    path
+}
+
+/// Download `whisper_path` if it is missing and names a known model.
+fn ensure_model(whisper_path: &Path, args: &Args) -> Result<(), Box<dyn Error>> {
+    if whisper_path.exists() && !args.model.force_download {
+        return Ok(());
+    }
+    let config = download::DownloadConfig {
+        base_url: args.model.model_url.as_deref(),
+        prefix: &args.model.model_prefix,
+        retries: args.model.download_retries,
+        token: args.model.hf_token.as_deref(),
+    };
+    match known_model_name(whisper_path) {
+        Some(name) => download::download_ggml_model(name, whisper_path, &config)
+            .map_err(|e| format!("Failed to download model {}: {}", name, e).into()),
+        None if whisper_path.exists() => {
+            eprintln!(
+                "Warning: {} is not a known model, so it cannot be downloaded again",
+                whisper_path.display()
+            );
+            Ok(())
+        }
+        None => Err(format!("Model not found at {}", whisper_path.display()).into()),
+    }
+}
+
+/// Download each of the named known models that isn't present yet. Returns
+/// whether all of them are available afterwards.
+fn download_models(download: &DownloadArgs) -> bool {
+    let config = download::DownloadConfig {
+        base_url: download.model_url.as_deref(),
+        prefix: &download.model_prefix,
+        retries: download.download_retries,
+        token: download.hf_token.as_deref(),
+    };
+    let mut all_ok = true;
+    for name_arg in &download.models {
+        let Some(name) = canonical_model_name(name_arg) else {
+            eprintln!("Unknown model {}; run list-models to see the known ones", name_arg);
+            all_ok = false;
+            continue;
+        };
+        announce_model_alias(name_arg);
+        let dest = PathBuf::from(format!("ggml-{}.bin", name));
+        if dest.exists() && !download.force_download {
+            println!("{} already exists", dest.display());
+            continue;
+        }
+        if let Err(e) = download::download_ggml_model(name, &dest, &config) {
+            eprintln!("Failed to download model {}: {}", name, e);
+            all_ok = false;
+        }
+    }
+    all_ok
+}
+
+/// Transcribe each input with every benchmark model and print a comparison table.
+fn run_benchmark(args: &Args, models: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut complete = true;
+    for audio_path_str in &args.audio_paths {
+        let mut rows = Vec::new();
+        for model in models {
+            announce_model_alias(model);
+            let model_path = resolve_model(model);
+            if let Err(e) = ensure_model(&model_path, args) {
+                eprintln!("{}", e);
+                continue;
+            }
+            // Tag outputs with the model so runs don't overwrite each other
+            let label = model_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| model.clone());
+            let loaded = match load_model(&model_path, args) {
+                Ok(ctx) => Model::new(ctx),
+                Err(e) => {
+                    eprintln!("Failed to load model {}: {}", model_path.display(), e);
+                    continue;
+                }
+            };
+            match process_file(args, &loaded, &model_path, audio_path_str, Some(&label)) {
+                Ok((outputs, report)) => rows.push((label, report, outputs.srt)),
+                Err(e) => eprintln!("{}", e),
+            }
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+
+        println!("\nBenchmark for {}:", audio_path_str);
+        println!("{:<28} {:>8} {:>9}  OUTPUT", "MODEL", "RTF", "SEGMENTS");
+        for (label, report, srt) in &rows {
+            println!(
+                "{:<28} {:>7.2}x {:>9}  {}",
+                label,
+                report.duration_secs / report.wall_secs.max(f64::EPSILON),
+                report.segment_count,
+                srt.display()
+            );
+        }
+        complete &= rows.len() == models.len();
+    }
+
+    if !complete {
+        return Err("some models could not be benchmarked".into());
+    }
+    Ok(())
+}
+
+const SUMMARY_PATH: &str = "transcription_summary.json";
+
+/// Exit code when every input was transcribed but at least one had no speech.
+/// 1 is used for failures and 2 for invalid arguments.
+const EXIT_NO_SPEECH: i32 = 3;
+
+/// Outcome of processing one input, written to the batch summary.
+#[derive(Serialize)]
+struct FileSummary {
+    input: String,
+    /// Line of the `--manifest` file the input came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest_line: Option<usize>,
+    status: &'static str,
+    outputs: Vec<String>,
+    duration_secs: Option<f64>,
+    language: Option<String>,
+    error: Option<String>,
+}
+
+/// Inputs listed in a manifest with their 1-based line numbers. Blank lines and
+/// lines starting with `#` are skipped.
+fn parse_manifest(content: &str) -> Vec<(usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| (line_number, line.to_string()))
+        .collect()
+}
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// File name for a downloaded input: the last path segment of the URL without
+/// its query or fragment.
+fn url_file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() && !name.contains(':') => name.to_string(),
+        _ => "download".to_string(),
+    }
+}
+
+/// Move the outputs of `summaries` into the zip archive `bundle`, with a folder
+/// per input named after its file stem. Each summary's outputs are rewritten to
+/// `<bundle>:<folder>/<file>`; the loose files are only removed once the
+/// archive is complete.
+fn write_bundle(bundle: &Path, summaries: &mut [FileSummary]) -> Result<(), Box<dyn Error>> {
+    let mut archive = zip::ZipWriter::new(fs::File::create(bundle)?);
+    let options = zip::write::SimpleFileOptions::default();
+    let mut folders = HashSet::new();
+    let mut bundled = Vec::new();
+    for summary in summaries.iter_mut().filter(|summary| !summary.outputs.is_empty()) {
+        let name = if is_url(&summary.input) {
+            url_file_name(&summary.input)
+        } else {
+            summary.input.clone()
+        };
+        let stem = output_base(Path::new(&name)).to_string_lossy().into_owned();
+        // Inputs from different directories can share a stem
+        let mut folder = stem.clone();
+        let mut n = 2;
+        while !folders.insert(folder.clone()) {
+            folder = format!("{} ({})", stem, n);
+            n += 1;
+        }
+
+        for output in &mut summary.outputs {
+            let path = PathBuf::from(output.as_str());
+            let file_name = path.file_name().ok_or("output path has no file name")?;
+            let entry = format!("{}/{}", folder, file_name.to_string_lossy());
+            archive.start_file(entry.as_str(), options)?;
+            io::copy(&mut fs::File::open(&path)?, &mut archive)?;
+            *output = format!("{}:{}", bundle.display(), entry);
+            bundled.push(path);
+        }
+    }
+    archive.finish()?;
+
+    for path in bundled {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Write each cue as `utt_NNNN.wav`, its slice of `samples` as 16-bit PCM, and
+/// `utt_NNNN.txt`, its text, into `dir`. Numbering continues after the highest
+/// utterance already there, so batches and repeated runs add to one dataset.
+/// Returns the number of utterances written.
+fn export_utterances(
+    subtitles: &[Subtitle],
+    samples: &[f32],
+    dir: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut next = 1;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let index = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("utt_")?.strip_suffix(".wav")?.parse::<u32>().ok());
+        if let Some(index) = index {
+            next = next.max(index + 1);
+        }
+    }
+
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut count = 0;
+    for sub in subtitles {
+        let text = sub.text.trim();
+        let start = (sub.start_time_cs as usize * SAMPLE_RATE / 100).min(samples.len());
+        let end = (sub.end_time_cs as usize * SAMPLE_RATE / 100).min(samples.len());
+        if text.is_empty() || start >= end {
+            continue;
+        }
+
+        let stem = format!("utt_{:04}", next);
+        let mut writer = hound::WavWriter::create(dir.join(format!("{}.wav", stem)), spec)?;
+        for &sample in &samples[start..end] {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize()?;
+        fs::write(dir.join(format!("{}.txt", stem)), format!("{}\n", text))?;
+        next += 1;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn write_summary(summaries: &[FileSummary], path: &Path) -> Result<(), Box<dyn Error>> {
+    let out_file = fs::File::create(path)?;
+    serde_json::to_writer_pretty(out_file, summaries)?;
+    Ok(())
+}
+
+/// Convert an input to a 16kHz WAV in a temporary directory and read its samples.
+fn convert_with_temp_wav(
+    args: &Args,
+    audio_path_str: &str,
+    outputs: &OutputPaths,
+    filter: Option<&str>,
+    max_secs: Option<f64>,
+) -> Result<Vec<f32>, String> {
+    // Create temp directory per file
+    let temp_dir = create_temporary_directory()
+        .map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+
+    let output_path = temp_dir.path().join("converted_audio.wav");
+
+    // Ensure WAV compatibility
+    ensure_wav_compatibility(Path::new(audio_path_str), &output_path, filter, max_secs)
+        .map_err(|e| {
+            format!("Failed to ensure WAV compatibility for {}: {}", audio_path_str, e)
+        })?;
+
+    // Copy the converted audio out of the temp dir before it is removed
+    if let Some(keep_path) = &args.keep_converted_wav {
+        let keep_path = keep_path.as_ref().unwrap_or(&outputs.converted_wav);
+        match fs::copy(&output_path, keep_path) {
+            Ok(_) => status!("Converted audio kept at {}.", keep_path.display()),
+            Err(e) => eprintln!(
+                "Failed to keep converted audio at {}: {}",
+                keep_path.display(),
+                e
+            ),
+        }
+    }
+
+    // The header tells how much memory the samples need before any are read
+    let sample_count = WavReader::open(&output_path)
+        .map(|reader| reader.duration() as u64)
+        .map_err(|e| format!("Failed to parse WAV file for {}: {}", audio_path_str, e))?;
+    check_decoded_size(sample_count, audio_path_str, args.input.allow_large)?;
+    let samples = parse_wav_file(&output_path)
+        .map_err(|e| format!("Failed to parse WAV file for {}: {}", audio_path_str, e))?;
+
+    // Cleanup temp_dir
+    if let Err(e) = temp_dir.close() {
+        eprintln!("Failed to clean up temporary directory for {}: {}", audio_path_str, e);
+    }
+    Ok(samples)
+}
+
+/// Decode one input, or only its `channel`, to 16kHz mono samples, either
+/// through a pipe or a kept temporary WAV, and warn when it is too short to
+/// transcribe well. With `limit_secs`, decoding stops after that much audio,
+/// for callers that only look at the start of the input.
+fn load_samples(
+    args: &Args,
+    audio_path_str: &str,
+    outputs: &OutputPaths,
+    channel: Option<usize>,
+    limit_secs: Option<f64>,
+) -> Result<Vec<f32>, String> {
+    let audio_path = Path::new(audio_path_str);
+    if !audio_path.exists() {
+        return Err(format!("Error: Audio file does not exist at {}", audio_path_str));
+    }
+
+    // Probing first avoids decoding any of a long input; without ffprobe only
+    // the start of it is decoded before it is rejected
+    if args.input.max_duration.is_some()
+        && let Some(secs) = probe_duration(audio_path)
+    {
+        check_max_duration(secs, audio_path_str, args.input.max_duration)?;
+    }
+
+    let filter = input_filter_chain(args, channel);
+    let max_secs = [limit_secs, max_duration_decode_secs(args.input.max_duration)]
+        .into_iter()
+        .flatten()
+        .reduce(f64::min);
+    let samples = if args.stream_audio {
+        // The length isn't known up front, so the limit is enforced while reading
+        let max_samples =
+            (!args.input.allow_large).then_some(MAX_DECODED_BYTES as usize / size_of::<f32>());
+        let samples = decode_with_ffmpeg(audio_path, filter.as_deref(), max_samples, max_secs)
+            .map_err(|e| format!("Failed to decode audio from {}: {}", audio_path_str, e))?;
+        check_decoded_size(samples.len() as u64, audio_path_str, args.input.allow_large)?;
+        samples
+    } else {
+        convert_with_temp_wav(args, audio_path_str, outputs, filter.as_deref(), max_secs)?
+    };
+    if samples.is_empty() {
+        return Err(format!("No audio samples found in {}", audio_path_str));
+    }
+    check_decoded_duration(samples.len(), audio_path_str, args.input.max_duration)?;
+    if samples.len() < MIN_SAMPLES {
+        eprintln!(
+            "Warning: {} is only {:.2}s long; whisper may not produce any text",
+            audio_path_str,
+            samples.len() as f64 / SAMPLE_RATE as f64
+        );
+    }
+    Ok(samples)
+}
+
+/// Level below which `--trim-silence` treats a frame as silent.
+const SILENCE_THRESHOLD_DB: f32 = -45.0;
+/// Audio kept on either side of the speech, so soft word edges aren't cut off.
+const SILENCE_PADDING: usize = SAMPLE_RATE / 5;
+
+/// The range of `samples` from the first to the last 10ms frame louder than
+/// `threshold_db`, widened by `padding` samples on each side. Starts on a
+/// frame boundary so the trimmed lead-in is a whole number of centiseconds;
+/// empty when every frame is silent.
+fn speech_bounds(samples: &[f32], threshold_db: f32, padding: usize) -> Range<usize> {
+    const FRAME: usize = SAMPLE_RATE / 100;
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let loud = |frame: &[f32]| {
+        let mean_square = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        mean_square.sqrt() > threshold
+    };
+    let Some(first) = samples.chunks(FRAME).position(loud) else {
+        return 0..0;
+    };
+    let last = samples.chunks(FRAME).rposition(loud).unwrap_or(first);
+    let start = (first * FRAME).saturating_sub(padding) / FRAME * FRAME;
+    let end = ((last + 1) * FRAME + padding).min(samples.len());
+    start..end
+}
+
+/// Transcribe `samples` and write the outputs named after `name_path`.
+/// `label` identifies the audio in error messages. `media` is the file the
+/// samples came from, if there is a single one, for `--compare-embedded-subs`.
+#[allow(clippy::too_many_arguments)]
+fn transcribe_and_write(
+    args: &Args,
+    model: &Model,
+    whisper_path: &Path,
+    samples: Vec<f32>,
+    name_path: &Path,
+    output_tag: Option<&str>,
+    label: &str,
+    media: Option<&Path>,
+) -> Result<(OutputPaths, TranscriptionReport), String> {
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let outputs = OutputPaths::new(name_path, output_tag, &naming)
+        .map_err(|e| format!("Skipping {}: {}", label, e))?;
+
+    // Perform transcription
+    let live_srt = args.incremental_srt.then(|| outputs.srt.clone());
+    let (subtitles, report) = handle_transcription(
+        model,
+        whisper_path,
+        &samples,
+        chunk_size(args),
+        args,
+        live_srt.as_deref(),
+    )
+    .map_err(|e| format!("Transcription failed for {}: {}", label, e))?;
+
+    // The {lang} placeholder and --lang-suffix can only be filled in once the language was detected
+    let mut outputs = match report.language {
+        Some(lang) if args.out.output_template.is_some() || args.out.lang_suffix => {
+            let naming = OutputNaming::new(args, whisper_path, lang);
+            OutputPaths::new(name_path, output_tag, &naming)
+                .map_err(|e| format!("Skipping {}: {}", label, e))?
+        }
+        _ => outputs,
+    };
+    // The language may have moved the SRT away from the one written while transcribing
+    if let Some(live_srt) = &live_srt
+        && *live_srt != outputs.srt
+    {
+        let _ = fs::remove_file(live_srt);
+    }
+    let lang = report.language.unwrap_or(&args.whisper.language);
+    write_outputs(&subtitles, &mut outputs, args, lang)
+        .map_err(|e| format!("Failed to write outputs for {}: {}", label, e))?;
+    if let Some(dir) = &args.export_utterances {
+        let count = export_utterances(&subtitles, &samples, dir)
+            .map_err(|e| format!("Failed to export utterances for {}: {}", label, e))?;
+        status!("{} utterance(s) exported to {}.", count, dir.display());
+    }
+    if args.compare_embedded_subs
+        && let Some(media) = media
+    {
+        compare_with_embedded_subtitles(&subtitles, media, &outputs, label);
+    }
+
+    Ok((outputs, report))
+}
+
+/// Extract the first subtitle track of `media` to `<stem>_embedded.srt` and
+/// write where `subtitles` diverge from it to `<stem>_diff.txt`. A file without
+/// a usable track only gets a warning, since its transcript is already written.
+fn compare_with_embedded_subtitles(
+    subtitles: &[Subtitle],
+    media: &Path,
+    outputs: &OutputPaths,
+    label: &str,
+) {
+    let embedded_path = outputs.namer.name("_embedded", "srt");
+    let reference = match compare::extract_embedded_subtitles(media, &embedded_path) {
+        Ok(reference) => reference,
+        Err(e) => {
+            eprintln!("Warning: cannot compare {} with its subtitles: {}", label, e);
+            return;
+        }
+    };
+    let (report, word_error_rate) = compare::compare_transcripts(&reference, subtitles);
+    let diff_path = outputs.namer.name("_diff", "txt");
+    match fs::write(&diff_path, report) {
+        Ok(()) => status!(
+            "Word error rate against the embedded subtitles: {:.1}%; differences written to {}",
+            word_error_rate * 100.0,
+            diff_path.display()
+        ),
+        Err(e) => eprintln!("Failed to write {}: {}", diff_path.display(), e),
+    }
+}
+
+/// Convert and transcribe a single input, returning its output paths and the
+/// transcription report.
+fn process_file(
+    args: &Args,
+    model: &Model,
+    whisper_path: &Path,
+    audio_path_str: &str,
+    output_tag: Option<&str>,
+) -> Result<(OutputPaths, TranscriptionReport), String> {
+    let audio_path = Path::new(audio_path_str);
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let outputs = OutputPaths::new(audio_path, output_tag, &naming)
+        .map_err(|e| format!("Skipping {}: {}", audio_path_str, e))?;
+    let samples = load_samples(args, audio_path_str, &outputs, None, None)?;
+    transcribe_and_write(
+        args,
+        model,
+        whisper_path,
+        samples,
+        audio_path,
+        output_tag,
+        audio_path_str,
+        Some(audio_path),
+    )
+}
+
+/// Download `url` to a temporary file and transcribe it, writing the outputs to
+/// the current directory under the URL's file name.
+fn process_url(
+    args: &Args,
+    model: &Model,
+    whisper_path: &Path,
+    url: &str,
+) -> Result<(OutputPaths, TranscriptionReport), String> {
+    let name = PathBuf::from(url_file_name(url));
+    let temp_dir = create_temporary_directory()
+        .map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+    let local_path = temp_dir.path().join(&name);
+    let bytes = download::download_with_retries(url, args.model.download_retries)
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    fs::write(&local_path, bytes).map_err(|e| format!("Failed to save {}: {}", url, e))?;
+    let local_path_str = local_path
+        .to_str()
+        .ok_or_else(|| format!("Temporary path for {} is not valid UTF-8", url))?;
+
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let outputs = OutputPaths::new(&name, None, &naming)
+        .map_err(|e| format!("Skipping {}: {}", url, e))?;
+    let samples = load_samples(args, local_path_str, &outputs, None, None)?;
+    transcribe_and_write(
+        args,
+        model,
+        whisper_path,
+        samples,
+        &name,
+        None,
+        url,
+        Some(&local_path),
+    )
+}
+
+/// Join every input, in order, into one sample stream and transcribe it as a
+/// single recording so timestamps run on across file boundaries.
+fn process_concat(
+    args: &Args,
+    model: &Model,
+    whisper_path: &Path,
+) -> Result<(OutputPaths, TranscriptionReport), String> {
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let mut samples = Vec::new();
+    for audio_path_str in &args.audio_paths {
+        // Each part keeps its own converted WAV name when --keep-converted-wav is used
+        let part_outputs = OutputPaths::new(Path::new(audio_path_str), None, &naming)
+            .map_err(|e| format!("Skipping {}: {}", audio_path_str, e))?;
+        samples.extend(load_samples(args, audio_path_str, &part_outputs, None, None)?);
+    }
+
+    let name_path = match &args.output_name {
+        Some(name) => name.clone(),
+        None => PathBuf::from(&args.audio_paths[0]),
+    };
+    transcribe_and_write(
+        args,
+        model,
+        whisper_path,
+        samples,
+        &name_path,
+        None,
+        "the concatenated inputs",
+        None,
+    )
+}
+
+/// Transcribe each channel of a multitrack input on its own and write one
+/// transcript of all of them, each cue prefixed with its channel's speaker.
+fn process_channels(
+    args: &Args,
+    model: &Model,
+    whisper_path: &Path,
+    audio_path_str: &str,
+) -> Result<(OutputPaths, TranscriptionReport), String> {
+    let audio_path = Path::new(audio_path_str);
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let outputs = OutputPaths::new(audio_path, None, &naming)
+        .map_err(|e| format!("Skipping {}: {}", audio_path_str, e))?;
+
+    let mut subtitles = Vec::new();
+    let mut language = None;
+    let mut duration_secs: f64 = 0.0;
+    let mut wall_secs = 0.0;
+    for (channel, speaker) in args.per_channel_speakers.iter().enumerate() {
+        status!("Channel {} ({}):", channel + 1, speaker);
+        let samples = load_samples(args, audio_path_str, &outputs, Some(channel), None)?;
+        let (channel_subtitles, report) =
+            handle_transcription(model, whisper_path, &samples, chunk_size(args), args, None)
+                .map_err(|e| format!("Transcription failed for {}: {}", audio_path_str, e))?;
+        subtitles.extend(channel_subtitles.into_iter().map(|sub| Subtitle {
+            text: format!("{}: {}", speaker, sub.text.trim()),
+            speaker: Some(speaker.clone()),
+            ..sub
+        }));
+        language = language.or(report.language);
+        duration_secs = duration_secs.max(report.duration_secs);
+        wall_secs += report.wall_secs;
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    // The sort is stable, so simultaneous cues keep the speakers' order
+    subtitles.sort_by_key(|sub| sub.start_time_cs);
+    renumber_subtitles(&mut subtitles);
+
+    let mut outputs = match language {
+        Some(lang) if args.out.output_template.is_some() || args.out.lang_suffix => {
+            let naming = OutputNaming::new(args, whisper_path, lang);
+            OutputPaths::new(audio_path, None, &naming)
+                .map_err(|e| format!("Skipping {}: {}", audio_path_str, e))?
+        }
+        _ => outputs,
+    };
+    write_outputs(&subtitles, &mut outputs, args, language.unwrap_or(&args.whisper.language))
+        .map_err(|e| format!("Failed to write outputs for {}: {}", audio_path_str, e))?;
+
+    let report = TranscriptionReport {
+        language,
+        duration_secs,
+        wall_secs,
+        segment_count: subtitles.len(),
+        tail: transcript_tail(&subtitles, PROMPT_TAIL_CHARS),
+    };
+    Ok((outputs, report))
+}
+
+/// Run whisper's language detection on the first chunk of `samples`, returning
+/// the most likely language id and its probability. With `--lang-candidates`
+/// only those languages are considered.
+fn detect_language(
+    ctx: &WhisperContext,
+    samples: &[f32],
+    candidates: &[c_int],
+) -> Result<(c_int, f32), Box<dyn Error>> {
+    let mut state = ctx.create_state()?;
+    let first_chunk = &samples[..samples.len().min(CHUNK_SIZE)];
+    let (lang_id, probs) = lang_probabilities(&mut state, first_chunk)?;
+    let lang_id = most_likely_candidate(&probs, candidates).unwrap_or(lang_id);
+    let probability = probs.get(lang_id as usize).copied().unwrap_or_default();
+    Ok((lang_id, probability))
+}
+
+/// whisper's most likely language for `samples` and the probability of every
+/// language, indexed by id.
+fn lang_probabilities(
+    state: &mut WhisperState,
+    samples: &[f32],
+) -> Result<(c_int, Vec<f32>), WhisperError> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(4));
+    state.pcm_to_mel(samples, threads)?;
+    state.lang_detect(0, threads)
+}
+
+/// The id in `candidates` with the highest probability in `probs`, preferring
+/// the earliest on ties, or `None` without candidates.
+fn most_likely_candidate(probs: &[f32], candidates: &[c_int]) -> Option<c_int> {
+    let probability = |id: c_int| probs.get(id as usize).copied().unwrap_or_default();
+    // max_by returns the last maximum, so reverse to prefer the earliest candidate
+    candidates
+        .iter()
+        .rev()
+        .copied()
+        .max_by(|&a, &b| probability(a).total_cmp(&probability(b)))
+}
+
+/// The ids of the `--lang-candidates` languages; unknown codes were rejected by `validate`.
+fn lang_candidate_ids(args: &Args) -> Vec<c_int> {
+    args.whisper.lang_candidates.iter().filter_map(|lang| whisper_rs::get_lang_id(lang)).collect()
+}
+
+/// Print the detected language of every input without transcribing it.
+/// Returns whether all inputs could be checked.
+fn run_language_detection(args: &Args, ctx: &WhisperContext, whisper_path: &Path) -> bool {
+    if !ctx.is_multilingual() {
+        eprintln!("{} is English-only and cannot detect languages", whisper_path.display());
+        return false;
+    }
+
+    let naming = OutputNaming::new(args, whisper_path, &args.whisper.language);
+    let candidates = lang_candidate_ids(args);
+    let first_chunk = Some((CHUNK_SIZE / SAMPLE_RATE) as f64);
+    let mut all_ok = true;
+    for audio_path_str in &args.audio_paths {
+        let result = OutputPaths::new(Path::new(audio_path_str), None, &naming)
+            .map_err(|e| format!("Skipping {}: {}", audio_path_str, e))
+            // Only the first chunk is used, so there is no need to decode the rest
+            .and_then(|outputs| load_samples(args, audio_path_str, &outputs, None, first_chunk))
+            .and_then(|samples| {
+                detect_language(ctx, &samples, &candidates).map_err(|e| {
+                    format!("Language detection failed for {}: {}", audio_path_str, e)
+                })
+            });
+        match result {
+            Ok((lang_id, probability)) => {
+                let lang = whisper_rs::get_lang_str(lang_id).unwrap_or("unknown");
+                if args.progress == ProgressMode::Json {
+                    let event = serde_json::json!({
+                        "event": "language",
+                        "input": audio_path_str,
+                        "language": lang,
+                        "probability": probability,
+                    });
+                    println!("{}", event);
+                } else {
+                    println!(
+                        "{}: {} ({}), probability {:.1}%",
+                        audio_path_str,
+                        lang,
+                        whisper_rs::get_lang_str_full(lang_id).unwrap_or("unknown"),
+                        probability * 100.0
+                    );
+                }
+            }
             Err(e) => {
-                eprintln!("Failed to ensure WAV compatibility for {}: {}", audio_path_str, e);
-                continue;
+                eprintln!("{}", e);
+                all_ok = false;
+            }
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    all_ok
+}
+
+/// The SRT output for messages: its path, or the range of split parts.
+fn describe_srt_output(outputs: &OutputPaths) -> String {
+    match (outputs.srt_parts.first(), outputs.srt_parts.last()) {
+        (Some(first), Some(last)) => format!(
+            "{} SRT parts ({} .. {})",
+            outputs.srt_parts.len(),
+            first.display(),
+            last.display()
+        ),
+        _ => outputs.srt.display().to_string(),
+    }
+}
+
+/// Print where the outputs went and return their paths for the batch summary.
+fn announce_outputs(outputs: &OutputPaths, args: &Args) -> Vec<String> {
+    let mut written = Vec::new();
+    for format in output_formats(args) {
+        if format == OutputFormat::Srt && !outputs.srt_parts.is_empty() {
+            status!("SRT output written to {}.", describe_srt_output(outputs));
+            written.extend(outputs.srt_parts.clone());
+            continue;
+        }
+        let path = outputs.path(format, &*format.writer(args));
+        status!("{} output written to {}.", format.label(), path.display());
+        written.push(path);
+    }
+    written
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect()
+}
+
+const EXAMPLES: &str = "\
+Examples:
+  audio-transcriber interview.mp3
+  audio-transcriber transcribe --model-path base.en --csv a.wav b.m4a
+  audio-transcriber --language auto --output-template '{stem}.{lang}.{ext}' talk.mp4
+  audio-transcriber download base.en small
+  audio-transcriber detect-lang --model-path base talk.mp4
+  audio-transcriber probe --progress json talk.mp4
+  audio-transcriber reformat talk_timestamps.srt --max-segment-length 4";
+
+// Usage: {} [transcribe] <path_to_wav_file>... | download | detect-lang | list-models | reformat
+#[derive(Parser)]
+#[command(
+    after_help = EXAMPLES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    action: Option<Action>,
+    // Without a subcommand the arguments are those of `transcribe`, as before subcommands existed
+    #[command(flatten)]
+    transcribe: TranscribeArgs,
+}
+
+impl Cli {
+    fn into_action(self) -> Action {
+        self.action
+            .unwrap_or_else(|| Action::Transcribe(Box::new(self.transcribe)))
+    }
+}
+
+#[derive(clap::Subcommand)]
+enum Action {
+    /// Transcribe audio files (the default when no subcommand is given)
+    Transcribe(Box<TranscribeArgs>),
+    /// Download known models to ggml-<name>.bin in the working directory
+    Download(DownloadArgs),
+    /// Print the language of each input's first 30 seconds
+    DetectLang(DetectLangArgs),
+    /// List known models with their download sizes and languages
+    ListModels,
+    /// Re-emit an existing SRT through the output pipeline instead of transcribing
+    Reformat(ReformatArgs),
+    /// Keep the model loaded and transcribe audio POSTed to /transcribe
+    Serve(ServeArgs),
+    /// Print each input's duration, codec, channels, sample rate and bit rate
+    Probe(ProbeArgs),
+}
+
+#[derive(clap::Args)]
+struct TranscribeArgs {
+    #[arg(
+        help = "Path to the audio containing file",
+        required_unless_present_any = [
+            "list_models",
+            "reformat",
+            "mic",
+            "model_info",
+            "manifest",
+            "check_gpu",
+        ],
+        conflicts_with = "mic",
+        num_args = 1..
+    )]
+    audio_paths: Vec<String>, // Paths to the audio files
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args)]
+struct DownloadArgs {
+    #[arg(required = true, num_args = 1.., help = "Known model names, such as base.en")]
+    models: Vec<String>,
+    #[arg(long, default_value_t = 3, help = "Number of times to retry a failed download")]
+    download_retries: u32,
+    #[arg(long, help = "Download models again even if they already exist")]
+    force_download: bool,
+    #[arg(
+        long,
+        env = "HF_TOKEN",
+        hide_env_values = true,
+        help = "Hugging Face access token for models that require one"
+    )]
+    hf_token: Option<String>,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Base URL to download models from (default: the Hugging Face mirror)"
+    )]
+    model_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "ggml-",
+        help = "File name prefix of models at --model-url"
+    )]
+    model_prefix: String,
+}
+
+#[derive(clap::Args)]
+struct DetectLangArgs {
+    #[arg(required = true, num_args = 1.., help = "Audio files to identify")]
+    audio_paths: Vec<String>,
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        help = "Only detect among these comma-separated languages"
+    )]
+    lang_candidates: Vec<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "bar",
+        help = "Output: human-readable text, or one JSON line per input"
+    )]
+    progress: ProgressMode,
+    #[command(flatten)]
+    model: ModelArgs,
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+#[derive(clap::Args)]
+struct ProbeArgs {
+    #[arg(required = true, num_args = 1.., help = "Audio or video files to inspect")]
+    audio_paths: Vec<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "bar",
+        help = "Output: human-readable text, or one JSON line per input"
+    )]
+    progress: ProgressMode,
+}
+
+#[derive(clap::Args)]
+struct ReformatArgs {
+    #[arg(help = "SRT file to re-emit")]
+    srt: PathBuf,
+    #[arg(
+        long,
+        default_value = "en",
+        help = "Language of the SRT, for {lang} in --output-template, --lang-suffix and --rtl auto"
+    )]
+    language: String,
+    #[command(flatten)]
+    text: TextArgs,
+    #[command(flatten)]
+    out: OutputArgs,
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    #[arg(long, default_value_t = 8080, help = "Port to listen on")]
+    port: u16,
+    #[arg(
+        long,
+        default_value = "127.0.0.1",
+        help = "Address to listen on, IPv4 or IPv6; 0.0.0.0 or :: accepts other machines too"
+    )]
+    host: IpAddr,
+    #[command(flatten)]
+    model: ModelArgs,
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    whisper: WhisperArgs,
+    #[command(flatten)]
+    text: TextArgs,
+}
+
+impl TranscribeArgs {
+    fn into_args(self) -> Args {
+        Args {
+            audio_paths: self.audio_paths,
+            ..self.args
+        }
+    }
+}
+
+impl DetectLangArgs {
+    fn into_args(self) -> Args {
+        let defaults = Args::defaults();
+        Args {
+            audio_paths: self.audio_paths,
+            detect_language: true,
+            progress: self.progress,
+            model: self.model,
+            input: self.input,
+            whisper: WhisperArgs {
+                lang_candidates: self.lang_candidates,
+                ..defaults.whisper
+            },
+            ..defaults
+        }
+    }
+}
+
+impl ReformatArgs {
+    fn into_args(self) -> Args {
+        let defaults = Args::defaults();
+        Args {
+            reformat: Some(self.srt),
+            whisper: WhisperArgs {
+                language: self.language,
+                ..defaults.whisper
+            },
+            text: self.text,
+            out: self.out,
+            ..defaults
+        }
+    }
+}
+
+impl ServeArgs {
+    fn into_args(self) -> Args {
+        Args {
+            serve: Some(SocketAddr::new(self.host, self.port)),
+            model: self.model,
+            input: self.input,
+            whisper: self.whisper,
+            text: self.text,
+            ..Args::defaults()
+        }
+    }
+}
+
+impl Args {
+    /// Every option at its default, for the subcommands that take only some groups of them.
+    fn defaults() -> Args {
+        let command = <Args as clap::Args>::augment_args(clap::Command::new("defaults"));
+        let matches = command.get_matches_from(["defaults"]);
+        <Args as clap::FromArgMatches>::from_arg_matches(&matches)
+            .expect("every option has a default")
+    }
+}
+
+// Options of transcribe, which the other subcommands take in groups
+#[derive(clap::Args)]
+struct Args {
+    // Filled in from the positional arguments of the subcommand
+    #[arg(skip)]
+    audio_paths: Vec<String>, // Paths to the audio files
+    #[arg(long, hide = true, help = "List known models with their download sizes and exit")]
+    list_models: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Keep the converted 16kHz WAV fed to whisper (default: <stem>_16k.wav)"
+    )]
+    keep_converted_wav: Option<Option<PathBuf>>,
+    #[arg(
+        long,
+        conflicts_with = "keep_converted_wav",
+        help = "Pipe decoded audio from ffmpeg instead of writing a temporary WAV"
+    )]
+    stream_audio: bool,
+    #[arg(
+        long,
+        hide = true,
+        value_name = "SRT",
+        help = "Re-emit an existing SRT through the output pipeline instead of transcribing"
+    )]
+    reformat: Option<PathBuf>,
+    #[arg(long, help = "Show the resolved model, inputs and output paths, then exit")]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_name = "MODELS",
+        value_delimiter = ',',
+        conflicts_with = "output",
+        help = "Compare comma-separated models (paths or known names) on the inputs"
+    )]
+    benchmark: Vec<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "bar",
+        help = "Progress output: a terminal bar, or JSON lines on stderr"
+    )]
+    progress: ProgressMode,
+    #[arg(
+        short,
+        long,
+        help = "Log each chunk's segment count and first words as it is transcribed"
+    )]
+    verbose: bool,
+    #[arg(
+        long,
+        help = "Print each segment to stdout as a JSON line as soon as its chunk is decoded"
+    )]
+    stream_jsonl: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["split_output", "per_channel_speakers", "no_timestamps"],
+        help = "Append each chunk's cues to the SRT as soon as they are decoded, so it can be \
+                read during a long job; it is rewritten with the final cues at the end"
+    )]
+    incremental_srt: bool,
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        conflicts_with_all = ["concat", "keep_converted_wav", "export_utterances", "output"],
+        help = "Transcribe each channel separately and label its cues with these names, in \
+                channel order"
+    )]
+    per_channel_speakers: Vec<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["offset", "timestamp_base", "no_timestamps"],
+        help = "Also write each cue as utt_NNNN.wav and utt_NNNN.txt into DIR"
+    )]
+    export_utterances: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with_all = ["concat", "per_channel_speakers"],
+        help = "Extract a video's first subtitle track and write where the transcript differs \
+                from it (<stem>_diff.txt)"
+    )]
+    compare_embedded_subs: bool,
+    #[arg(
+        long,
+        help = "Transcribe live from the default microphone (needs the `mic` feature)"
+    )]
+    mic: bool,
+    #[arg(
+        long,
+        conflicts_with = "benchmark",
+        help = "Transcribe all inputs, in order, as one recording with continuous timestamps"
+    )]
+    concat: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "concat",
+        help = "Name --concat outputs after this path instead of the first input"
+    )]
+    output_name: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "ZIP",
+        conflicts_with = "concat",
+        help = "Move every input's outputs into this zip archive, one folder per input"
+    )]
+    bundle: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "concat",
+        help = "Prompt each input with the end of the previous input's transcript"
+    )]
+    prompt_from_previous: bool,
+    // Set between inputs by --prompt-from-previous
+    #[arg(skip)]
+    previous_transcript: Option<String>,
+    // Address to listen on, set by the serve subcommand
+    #[arg(skip)]
+    serve: Option<SocketAddr>,
+    #[arg(
+        long,
+        requires = "per_channel_speakers",
+        help = "Colour each speaker's SRT cues with <font> tags, which some players ignore"
+    )]
+    color_speakers: bool,
+    #[arg(
+        long,
+        hide = true,
+        conflicts_with_all = ["benchmark", "concat"],
+        help = "Print the language of each input's first 30 seconds, then exit"
+    )]
+    detect_language: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["csv", "confidence_heatmap", "format", "max_len", "reformat"],
+        help = "Skip timestamp decoding and write only the raw transcript"
+    )]
+    no_timestamps: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["concat", "benchmark", "detect_language"],
+        help = "Also transcribe each path or URL listed in FILE, one per line (# for comments)"
+    )]
+    manifest: Option<PathBuf>,
+    #[arg(long, help = "Print the compiled whisper backend and CPU features, then exit")]
+    check_gpu: bool,
+    #[arg(long, help = "Load the model, print its type, size and dimensions, then exit")]
+    model_info: bool,
+    #[command(flatten)]
+    model: ModelArgs,
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    whisper: WhisperArgs,
+    #[command(flatten)]
+    text: TextArgs,
+    #[command(flatten)]
+    out: OutputArgs,
+}
+
+// Loading the model, and downloading it and FFmpeg
+#[derive(clap::Args)]
+struct ModelArgs {
+    #[arg(
+        long,
+        alias = "model",
+        help = "Path to the model, or a known model name or alias such as base.en or turbo"
+    )]
+    model_path: Option<String>, // Path to the model
+    #[arg(long, help = "Use flash attention")]
+    fa: bool, // Use flash attention
+    #[arg(long, default_value_t = 3, help = "Number of times to retry a failed download")]
+    download_retries: u32,
+    #[arg(long, help = "Never download FFmpeg; fail if it is not installed")]
+    no_download_ffmpeg: bool,
+    #[arg(
+        long,
+        value_name = "HEX",
+        conflicts_with = "no_download_ffmpeg",
+        help = "Expected SHA-256 of the FFmpeg archive; a download that doesn't match is refused"
+    )]
+    ffmpeg_sha256: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "no_download_ffmpeg",
+        help = "Download the model and FFmpeg again even if they exist, replacing them"
+    )]
+    force_download: bool,
+    #[arg(
+        long,
+        env = "HF_TOKEN",
+        hide_env_values = true,
+        help = "Hugging Face access token for models that require one"
+    )]
+    hf_token: Option<String>,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Base URL to download models from (default: the Hugging Face mirror)"
+    )]
+    model_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "ggml-",
+        help = "File name prefix of models at --model-url"
+    )]
+    model_prefix: String,
+    #[arg(long, value_name = "ID", help = "GPU to run on when several are available")]
+    gpu_device: Option<c_int>,
+    #[arg(
+        long,
+        conflicts_with = "gpu_device",
+        help = "Run on the CPU even if this build has a GPU backend"
+    )]
+    cpu_only: bool,
+    #[arg(
+        long,
+        conflicts_with = "cpu_only",
+        help = "If the GPU runs out of memory, reload the model on the CPU and carry on"
+    )]
+    gpu_fallback_cpu: bool,
+}
+
+// Decoding and filtering the input audio
+#[derive(clap::Args)]
+struct InputArgs {
+    #[arg(long, help = "Load inputs that need more than 2 GiB of memory once decoded")]
+    allow_large: bool,
+    #[arg(
+        long,
+        help = "Skip silence at the start and end of each input; timestamps still match the input"
+    )]
+    trim_silence: bool,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Refuse inputs longer than this, checked with ffprobe before decoding when it \
+                is installed"
+    )]
+    max_duration: Option<f64>,
+    #[arg(long, value_name = "HZ", help = "Remove rumble below this frequency before transcribing")]
+    highpass: Option<u32>,
+    #[arg(long, value_name = "HZ", help = "Remove hiss above this frequency before transcribing")]
+    lowpass: Option<u32>,
+    #[arg(long, help = "Run ffmpeg's FFT denoiser (afftdn) before transcribing")]
+    denoise: bool,
+    #[arg(long, help = "Speech band filter: --highpass 80 --lowpass 8000 unless set explicitly")]
+    clean_speech: bool,
+}
+
+// Whisper's decoding parameters
+#[derive(clap::Args)]
+struct WhisperArgs {
+    #[arg(
+        long,
+        default_value = "en",
+        help = "Spoken language code, or 'auto' to let whisper detect it per chunk"
+    )]
+    language: String,
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        help = "With --language auto, only detect among these comma-separated languages"
+    )]
+    lang_candidates: Vec<String>,
+    #[arg(
+        long,
+        value_name = "BOOL",
+        help = "Suppress blank outputs at the start of sampling (whisper default: true)"
+    )]
+    suppress_blank: Option<bool>,
+    #[arg(
+        long,
+        value_name = "IDS",
+        value_delimiter = ',',
+        help = "Comma-separated token ids that whisper may never emit"
+    )]
+    suppress_tokens: Vec<c_int>,
+    #[arg(long, help = "Don't use text from previous chunks as context")]
+    no_context: bool,
+    #[arg(long, help = "Emit a single segment per chunk")]
+    single_segment: bool,
+    #[arg(
+        long,
+        value_name = "CHARS",
+        help = "Maximum characters per segment (enables token timestamps)"
+    )]
+    max_len: Option<c_int>,
+    #[arg(long, value_name = "N", help = "Maximum tokens per segment")]
+    max_tokens: Option<c_int>,
+    #[arg(
+        long,
+        value_name = "PROB",
+        default_value_t = 0.6,
+        help = "Drop a segment as silence when its no-speech probability exceeds this \
+                and its average log probability is below --logprob-threshold (1 disables)"
+    )]
+    no_speech_threshold: f32,
+    #[arg(
+        long,
+        value_name = "LOGPROB",
+        default_value_t = -1.0,
+        allow_negative_numbers = true,
+        help = "Average token log probability below which whisper retries a chunk at a higher \
+                temperature, or drops it when it is likely silent"
+    )]
+    logprob_threshold: f32,
+    #[arg(
+        long,
+        value_name = "ENTROPY",
+        default_value_t = 2.4,
+        help = "Token entropy below which output counts as repetitive and whisper retries the \
+                chunk at a higher temperature"
+    )]
+    entropy_threshold: f32,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Encoder positions per chunk (20ms each, max 1500 = 30s); smaller values use \
+                less memory, shorten chunks to match and cost some accuracy"
+    )]
+    audio_ctx: Option<c_int>,
+    #[arg(
+        long,
+        value_name = "TOKENS",
+        help = "Maximum tokens of earlier text kept as context (whisper default: 16384); \
+                smaller values mean less decoding work per chunk"
+    )]
+    max_text_ctx: Option<c_int>,
+    #[arg(
+        long,
+        value_name = "TEXT",
+        help = "Initial prompt that biases whisper's style and spelling"
+    )]
+    prompt: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "prompt",
+        help = "Don't prime whisper with the built-in prompt for --language (ja, zh, ko, ...)"
+    )]
+    no_default_prompt: bool,
+}
+
+// Cleaning up and retiming the subtitles before they are written
+#[derive(clap::Args)]
+struct TextArgs {
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Split subtitle cues longer than this many seconds"
+    )]
+    max_segment_length: Option<f32>,
+    #[arg(long, help = "Remove [...] and (...) annotations such as [Music] from the text")]
+    strip_brackets: bool,
+    #[arg(
+        long,
+        help = "Remove fillers such as um and uh and repeated words from every output but the \
+                raw transcript"
+    )]
+    strip_fillers: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "strip_fillers",
+        help = "Newline-separated fillers to remove instead of the built-in list; write one \
+                between commas (,like,) to remove it only where punctuation sets it off"
+    )]
+    filler_file: Option<PathBuf>,
+    // Read from --filler-file, or the built-in list, once arguments are validated
+    #[arg(skip)]
+    fillers: Vec<String>,
+    #[arg(
+        long,
+        value_name = "CS",
+        help = "Merge cues shorter than this many centiseconds into a neighbour"
+    )]
+    min_cue_duration: Option<u64>,
+    #[arg(
+        long,
+        value_name = "MS",
+        allow_negative_numbers = true,
+        help = "Shift all timestamps by this many milliseconds (may be negative)"
+    )]
+    offset: Option<i64>,
+    #[arg(
+        long,
+        value_name = "PATTERN=>TEXT",
+        value_parser = parse_replacement,
+        help = "Replace regex matches in each segment's text, e.g. 'colou?r=>color' (repeatable)"
+    )]
+    replace: Vec<Replacement>,
+    #[arg(
+        long,
+        value_name = "HH:MM:SS",
+        value_parser = parse_timestamp_base,
+        help = "Place the timestamps on a longer recording's timeline, starting at this time"
+    )]
+    timestamp_base: Option<u64>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Newline-separated glossary of terms to add to the initial prompt"
+    )]
+    vocab: Option<PathBuf>,
+    #[arg(
+        long,
+        requires = "vocab",
+        help = "Respell words that nearly match a single-word --vocab term"
+    )]
+    vocab_correct: bool,
+    // Read from --vocab once arguments are validated
+    #[arg(skip)]
+    vocab_terms: Vec<String>,
+    #[arg(long, help = "Keep whisper's special tokens such as <|endoftext|> in the text")]
+    print_special: bool,
+    #[arg(
+        long,
+        help = "Start each cue with the language of its chunk, such as [en] or [es]"
+    )]
+    tag_language: bool,
+    #[arg(long, help = "Re-split subtitles so that each cue is one sentence")]
+    resegment_sentences: bool,
+}
+
+// Which output files are written, where and how
+#[derive(clap::Args)]
+struct OutputArgs {
+    #[arg(
+        long,
+        help = "Never overwrite existing outputs; write to '<name> (n).<ext>' instead"
+    )]
+    no_overwrite: bool,
+    #[arg(long, help = "Also write a CSV with one row per segment (<stem>_transcript.csv)")]
+    csv: bool,
+    #[arg(
+        long,
+        help = "Also write an HTML timeline coloured by segment confidence (<stem>_heatmap.html)"
+    )]
+    confidence_heatmap: bool,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Output file name template using {stem}, {ext}, {model}, {lang} and {date}"
+    )]
+    output_template: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["output_template", "output"],
+        help = "Put the forced or detected language before each extension (talk_raw.es.txt)"
+    )]
+    lang_suffix: bool,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Insert [HH:MM:SS] markers into the raw transcript every N seconds"
+    )]
+    raw_timestamp_interval: Option<u64>,
+    #[arg(
+        long,
+        value_name = "SRT",
+        conflicts_with = "timestamp_base",
+        help = "Also append the cues to this running SRT, continuing its numbering and times"
+    )]
+    append_to: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_output_path,
+        conflicts_with = "no_overwrite",
+        help = "Write the transcript to exactly this file, in the format its extension names \
+                (.srt, .vtt, .json, .txt, .csv, .lrc, .ass, .tsv or .html)"
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "utf8",
+        help = "Encoding of the SRT and text outputs"
+    )]
+    encoding: TextEncoding,
+    #[arg(long, help = "End SRT lines with CRLF instead of LF")]
+    crlf: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Start SRT lines with a right-to-left mark (auto: for Arabic, Hebrew, Persian, ...)"
+    )]
+    rtl: RtlMode,
+    #[arg(
+        long,
+        value_name = "FPS",
+        help = "Add start_frame and end_frame columns at this frame rate to the CSV"
+    )]
+    fps: Option<f32>,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Transcript formats to write, or all or none (default: raw,srt,txt)"
+    )]
+    format: Vec<OutputFormat>,
+    #[arg(
+        long,
+        help = "Time each word of the ASS output with \\k tags for karaoke-style highlighting"
+    )]
+    karaoke: bool,
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        help = "Write the SRT as one <name>_partNNN.srt file per this many minutes"
+    )]
+    split_output: Option<f32>,
+    #[arg(
+        long,
+        requires = "split_output",
+        help = "Keep absolute timestamps in split SRT parts instead of starting each at zero"
+    )]
+    split_absolute: bool,
+    #[arg(long, help = "Re-read each written SRT and fail if it is malformed")]
+    validate_output: bool,
+}
+
+/// Placeholders in an output template that `OutputPaths` doesn't know how to fill.
+fn unknown_placeholders(template: &str) -> Vec<&str> {
+    const KNOWN: &[&str] = &["{stem}", "{ext}", "{model}", "{lang}", "{date}"];
+    let mut unknown = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(len) = rest[open..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[open..=open + len];
+        if !KNOWN.contains(&placeholder) {
+            unknown.push(placeholder);
+        }
+        rest = &rest[open + len + 1..];
+    }
+    unknown
+}
+
+/// Check everything that can be checked before any conversion or model loading,
+/// returning every problem found rather than stopping at the first.
+fn validate(args: &Args) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let language = &args.whisper.language;
+    if language != "auto" && whisper_rs::get_lang_id(language).is_none() {
+        problems.push(format!("Unknown language code '{}'", args.whisper.language));
+    }
+    for lang in &args.whisper.lang_candidates {
+        if whisper_rs::get_lang_id(lang).is_none() {
+            problems.push(format!("Unknown language code '{}' in --lang-candidates", lang));
+        }
+    }
+    if !args.whisper.lang_candidates.is_empty() && language != "auto" && !args.detect_language {
+        problems.push("--lang-candidates needs --language auto".to_string());
+    }
+    if args.no_timestamps
+        && let Some(path) = &args.out.output
+        && output_path_format(path) != Some(OutputFormat::Raw)
+    {
+        problems.push(format!(
+            "--no-timestamps only writes the raw transcript, so --output {} needs a .txt extension",
+            path.display()
+        ));
+    }
+    if let Some(template) = &args.out.output_template {
+        for placeholder in unknown_placeholders(template) {
+            problems.push(format!("Unknown placeholder {} in --output-template", placeholder));
+        }
+        if !template.contains("{ext}") {
+            problems.push("--output-template must contain {ext} to tell outputs apart".to_string());
+        }
+    }
+    if args.mic && !cfg!(feature = "mic") {
+        problems.push("--mic needs a build with `--features mic`".to_string());
+    }
+    if args.out.fps.is_some_and(|fps| fps <= 0.0 || !fps.is_finite()) {
+        problems.push("--fps must be a positive frame rate".to_string());
+    }
+    if args.out.split_output.is_some_and(|minutes| minutes <= 0.0) {
+        problems.push("--split-output must be greater than zero".to_string());
+    }
+    if let Some(manifest) = &args.manifest
+        && let Err(e) = fs::metadata(manifest)
+    {
+        problems.push(format!("Cannot read manifest {}: {}", manifest.display(), e));
+    }
+    if let Some(vocab) = &args.text.vocab
+        && let Err(e) = fs::metadata(vocab)
+    {
+        problems.push(format!("Cannot read vocabulary {}: {}", vocab.display(), e));
+    }
+    if let Some(filler_file) = &args.text.filler_file
+        && let Err(e) = fs::metadata(filler_file)
+    {
+        problems.push(format!("Cannot read filler list {}: {}", filler_file.display(), e));
+    }
+    if args.text.max_segment_length.is_some_and(|secs| secs <= 0.0) {
+        problems.push("--max-segment-length must be greater than zero".to_string());
+    }
+    if args.input.max_duration.is_some_and(|secs| secs <= 0.0 || !secs.is_finite()) {
+        problems.push("--max-duration must be greater than zero".to_string());
+    }
+    if args.out.output.is_some() && args.audio_paths.len() > 1 && !args.concat {
+        problems.push(
+            "--output names a single file; use it with one input or --concat".to_string(),
+        );
+    }
+    if args.out.raw_timestamp_interval == Some(0) {
+        problems.push("--raw-timestamp-interval must be greater than zero".to_string());
+    }
+    if args.out.karaoke && !output_formats(args).contains(&OutputFormat::Ass) {
+        problems.push("--karaoke needs ASS output (--format ass)".to_string());
+    }
+    if args.incremental_srt && !output_formats(args).contains(&OutputFormat::Srt) {
+        problems.push("--incremental-srt needs SRT output".to_string());
+    }
+    if args.out.fps.is_some() && !output_formats(args).contains(&OutputFormat::Csv) {
+        problems.push("--fps needs CSV output (--csv or --format csv)".to_string());
+    }
+    if args.whisper.max_len.is_some_and(|len| len <= 0) {
+        problems.push("--max-len must be greater than zero".to_string());
+    }
+    if args.whisper.max_tokens.is_some_and(|tokens| tokens <= 0) {
+        problems.push("--max-tokens must be greater than zero".to_string());
+    }
+    if args.whisper.audio_ctx.is_some_and(|ctx| !(0..=1500).contains(&ctx)) {
+        problems.push("--audio-ctx must be between 0 and 1500".to_string());
+    }
+    if args.whisper.max_text_ctx.is_some_and(|ctx| ctx <= 0) {
+        problems.push("--max-text-ctx must be greater than zero".to_string());
+    }
+    if !(0.0..=1.0).contains(&args.whisper.no_speech_threshold) {
+        problems.push("--no-speech-threshold must be between 0 and 1".to_string());
+    }
+
+    // The model is only needed when something will actually be transcribed
+    let transcribes = !(args.list_models
+        || args.check_gpu
+        || args.reformat.is_some()
+        || args.dry_run
+        || !args.benchmark.is_empty());
+    let default_model = "ggml-large-v3-turbo.bin";
+    let model_path = resolve_model(args.model.model_path.as_deref().unwrap_or(default_model));
+    // Known models are downloaded on first use
+    if transcribes && !model_path.exists() && known_model_name(&model_path).is_none() {
+        problems.push(format!("Model not found at {}", model_path.display()));
+    }
+
+    // Outputs are named after each input's stem but written relative to the
+    // working directory (or wherever a template or --output points), so the
+    // directories of the resolved paths are the ones that must accept new files
+    let dir_of = |path: &Path| match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let naming = OutputNaming::new(args, &model_path, &args.whisper.language);
+    let mut output_dirs: Vec<PathBuf> = args
+        .audio_paths
+        .iter()
+        .filter_map(|input| OutputPaths::numbered(Path::new(input), None, &naming, None).ok())
+        .flat_map(|outputs| outputs.written_files(&naming))
+        .map(|path| dir_of(&path))
+        .collect();
+    let named_outputs = [
+        args.keep_converted_wav.as_ref().and_then(|path| path.as_ref()),
+        args.output_name.as_ref(),
+        args.out.output.as_ref(),
+        args.bundle.as_ref(),
+    ];
+    output_dirs.extend(named_outputs.into_iter().flatten().map(|path| dir_of(path)));
+    output_dirs.sort();
+    output_dirs.dedup();
+    for dir in output_dirs {
+        if tempfile::NamedTempFile::new_in(&dir).is_err() {
+            problems.push(format!("Output directory {} is not writable", dir.display()));
+        }
+    }
+
+    problems
+}
+
+fn main() {
+    let mut args = match Cli::parse().into_action() {
+        Action::Transcribe(transcribe) => transcribe.into_args(),
+        Action::DetectLang(detect) => detect.into_args(),
+        Action::Reformat(reformat) => reformat.into_args(),
+        Action::Serve(serve) => serve.into_args(),
+        Action::ListModels => {
+            print_known_models();
+            return;
+        }
+        Action::Download(download) => {
+            if !download_models(&download) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Action::Probe(probe) => {
+            if !probe::probe_inputs(&probe.audio_paths, probe.progress == ProgressMode::Json) {
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    let problems = validate(&args);
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("error: {}", problem);
+        }
+        std::process::exit(2);
+    }
+    if let Some(vocab) = &args.text.vocab {
+        match fs::read_to_string(vocab) {
+            Ok(content) => args.text.vocab_terms = parse_vocab(&content),
+            Err(e) => {
+                eprintln!("Failed to read vocabulary {}: {}", vocab.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if args.text.strip_fillers {
+        let content = match &args.text.filler_file {
+            Some(filler_file) => fs::read_to_string(filler_file).unwrap_or_else(|e| {
+                eprintln!("Failed to read filler list {}: {}", filler_file.display(), e);
+                std::process::exit(1);
+            }),
+            None => DEFAULT_FILLERS.to_string(),
+        };
+        args.text.fillers = parse_vocab(&content);
+    }
+    STDOUT_IS_JSONL.store(args.stream_jsonl, Ordering::Relaxed);
+
+    // First Ctrl-C finishes the current chunk and writes partial output; a second one quits
+    let handler = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!(
+            "\nInterrupt received, finishing the current chunk (press Ctrl-C again to abort)"
+        );
+    });
+    if let Err(e) = handler {
+        eprintln!("Failed to install Ctrl-C handler: {}", e);
+    }
+
+    if args.list_models {
+        print_known_models();
+        return;
+    }
+
+    if args.check_gpu {
+        println!("Whisper backend: {}", compiled_backend());
+        println!("System info: {}", whisper_rs::print_system_info().trim());
+        if compiled_backend() == "CPU" {
+            println!(
+                "No GPU backend compiled in; build with --features cuda, metal, vulkan or hipblas"
+            );
+        }
+        return;
+    }
+
+    if let Some(srt_path) = &args.reformat {
+        if let Err(e) = reformat_srt(srt_path, &args) {
+            eprintln!("Failed to reformat {}: {}", srt_path.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if !args.benchmark.is_empty() {
+        if let Err(e) = download_ffmpeg(
+            args.model.download_retries,
+            !args.model.no_download_ffmpeg,
+            args.model.force_download,
+            args.model.ffmpeg_sha256.as_deref(),
+        ) {
+            eprintln!("Failed to download FFmpeg: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = run_benchmark(&args, &args.benchmark) {
+            eprintln!("Benchmark incomplete: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Introduce a temporary binding for the default model path
+    let binding = "ggml-large-v3-turbo.bin".to_string();
+
+    // Use the temporary binding in unwrap_or
+    let model_arg = args.model.model_path.clone().unwrap_or(binding);
+    announce_model_alias(&model_arg);
+    let model_path = resolve_model(&model_arg);
+    let whisper_path = model_path.as_path();
+    if args.dry_run {
+        print_dry_run(&args, whisper_path);
+        return;
+    }
+
+    if let Err(e) = ensure_model(whisper_path, &args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    if args.model_info {
+        match load_model(whisper_path, &args) {
+            Ok(ctx) => print_model_info(&ctx, whisper_path),
+            Err(e) => {
+                eprintln!("Failed to load model {}: {}", whisper_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    #[cfg(feature = "mic")]
+    if args.mic {
+        let result = load_model(whisper_path, &args)
+            .and_then(|ctx| mic::transcribe_microphone(&ctx, &args));
+        if let Err(e) = result {
+            eprintln!("Microphone transcription failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Download FFmpeg if not already installed
+    match download_ffmpeg(
+        args.model.download_retries,
+        !args.model.no_download_ffmpeg,
+        args.model.force_download,
+        args.model.ffmpeg_sha256.as_deref(),
+    ) {
+        Ok(_) => (),
+        Err(e) => {
+            eprintln!("Failed to download FFmpeg: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Loading the model is slow, so do it once for every input
+    let model = match load_model(whisper_path, &args) {
+        Ok(ctx) => Model::new(ctx),
+        Err(e) => {
+            eprintln!("Failed to load model {}: {}", whisper_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(addr) = args.serve {
+        if let Err(e) = server::serve(addr, &model, whisper_path, &args) {
+            eprintln!("Server stopped: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.detect_language {
+        if !run_language_detection(&args, &model.ctx, whisper_path) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.concat {
+        match process_concat(&args, &model, whisper_path) {
+            Ok((outputs, report)) => {
+                announce_outputs(&outputs, &args);
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    std::process::exit(130);
+                }
+                if report.segment_count == 0 {
+                    status!("No speech detected in the concatenated inputs.");
+                    std::process::exit(EXIT_NO_SPEECH);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
+        return;
+    }
+
+    let mut inputs: Vec<(Option<usize>, String)> = args
+        .audio_paths
+        .iter()
+        .map(|path| (None, path.clone()))
+        .collect();
+    if let Some(manifest) = &args.manifest {
+        match fs::read_to_string(manifest) {
+            Ok(content) => inputs.extend(
+                parse_manifest(&content)
+                    .into_iter()
+                    .map(|(line, input)| (Some(line), input)),
+            ),
+            Err(e) => {
+                eprintln!("Failed to read manifest {}: {}", manifest.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Process each audio file, continuing past failures
+    let mut summaries = Vec::new();
+    for (manifest_line, audio_path_str) in &inputs {
+        let result = if is_url(audio_path_str) {
+            process_url(&args, &model, whisper_path, audio_path_str)
+        } else if !args.per_channel_speakers.is_empty() {
+            process_channels(&args, &model, whisper_path, audio_path_str)
+        } else {
+            process_file(&args, &model, whisper_path, audio_path_str, None)
+        };
+        if args.prompt_from_previous {
+            // A failed input breaks the chain, so the next one starts without context
+            args.previous_transcript = match &result {
+                Ok((_, report)) if !report.tail.is_empty() => Some(report.tail.clone()),
+                _ => None,
+            };
+        }
+        match result {
+            Ok((outputs, report)) => {
+                // The outputs are still written, as valid but empty files
+                let no_speech = report.segment_count == 0;
+                if no_speech {
+                    status!("No speech detected in {}.", audio_path_str);
+                }
+                summaries.push(FileSummary {
+                    input: audio_path_str.clone(),
+                    manifest_line: *manifest_line,
+                    status: if no_speech { "no_speech" } else { "ok" },
+                    outputs: announce_outputs(&outputs, &args),
+                    duration_secs: Some(report.duration_secs),
+                    language: report.language.map(str::to_string),
+                    error: None,
+                });
+            }
+            Err(e) => {
+                match manifest_line {
+                    Some(line) => eprintln!("Manifest line {}: {}", line, e),
+                    None => eprintln!("{}", e),
+                }
+                summaries.push(FileSummary {
+                    input: audio_path_str.clone(),
+                    manifest_line: *manifest_line,
+                    status: "failed",
+                    outputs: Vec::new(),
+                    duration_secs: None,
+                    language: None,
+                    error: Some(e),
+                });
+            }
+        }
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
 
-        let original_samples = match parse_wav_file(&output_path) {
-            Ok(samples) => samples,
+    let mut bundle_failed = false;
+    if let Some(bundle) = &args.bundle {
+        match write_bundle(bundle, &mut summaries) {
+            Ok(()) => status!("Outputs bundled into {}.", bundle.display()),
             Err(e) => {
-                eprintln!("Failed to parse WAV file for {}: {}", audio_path_str, e);
-                continue;
+                eprintln!("Failed to write bundle {}: {}", bundle.display(), e);
+                bundle_failed = true;
             }
+        }
+    }
+
+    if inputs.len() > 1 {
+        match write_summary(&summaries, Path::new(SUMMARY_PATH)) {
+            Ok(_) => status!("Batch summary written to {}.", SUMMARY_PATH),
+            Err(e) => eprintln!("Failed to write batch summary: {}", e),
+        }
+    }
+
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        std::process::exit(130);
+    }
+    if bundle_failed || summaries.iter().any(|summary| summary.error.is_some()) {
+        std::process::exit(1);
+    }
+    if summaries.iter().any(|summary| summary.status == "no_speech") {
+        std::process::exit(EXIT_NO_SPEECH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn subtitle(seq: u32, start_time_cs: u64, end_time_cs: u64, text: &str) -> Subtitle {
+        Subtitle {
+            seq,
+            start_time_cs,
+            end_time_cs,
+            text: text.to_string(),
+            words: Vec::new(),
+            lang: None,
+            confidence: None,
+            speaker: None,
+        }
+    }
+
+    fn parse_args(argv: &[&str]) -> Args {
+        match Cli::parse_from(argv).into_action() {
+            Action::Transcribe(transcribe) => transcribe.into_args(),
+            Action::DetectLang(detect) => detect.into_args(),
+            Action::Reformat(reformat) => reformat.into_args(),
+            Action::Serve(serve) => serve.into_args(),
+            _ => panic!("not an action with shared options"),
+        }
+    }
+
+    fn default_naming() -> OutputNaming {
+        OutputNaming {
+            template: None,
+            model: String::new(),
+            lang: "en".to_string(),
+            lang_suffix: false,
+            no_overwrite: false,
+            output: None,
+            writers: Vec::new(),
+            split_output: false,
+        }
+    }
+
+    #[test]
+    fn formats_srt_time() {
+        assert_eq!(cs_to_srt_time(0), "00:00:00,000");
+        assert_eq!(cs_to_srt_time(99), "00:00:00,990");
+        assert_eq!(cs_to_srt_time(6_000), "00:01:00,000");
+        assert_eq!(cs_to_srt_time(366_150), "01:01:01,500");
+    }
+
+    #[test]
+    fn short_clip_starts_at_zero() {
+        // A 2-second clip is a single chunk, so its first cue is not shifted
+        let args = parse_args(&["audio-transcriber", "in.wav"]);
+        let samples = vec![0.0; 2 * SAMPLE_RATE];
+        let chunks: Vec<&[f32]> = samples.chunks(chunk_size(&args)).collect();
+        assert_eq!(chunk_offsets_cs(0, &chunks), [0]);
+
+        let subs = postprocess_subtitles(vec![subtitle(1, 0, 180, " Hi.")], &args);
+        assert!(subtitles_to_srt(&subs, false).starts_with("1\n00:00:00,000 --> 00:00:01,800\n"));
+    }
+
+    #[test]
+    fn srt_time_does_not_wrap_after_24_hours() {
+        // 25 hours, 2 minutes, 3.45 seconds
+        assert_eq!(cs_to_srt_time(9_012_345), "25:02:03,450");
+    }
+
+    #[test]
+    fn formats_srt_cue() {
+        let sub = subtitle(3, 150, 420, " Hello there.");
+        assert_eq!(
+            subtitle_to_srt(&sub),
+            "3\n00:00:01,500 --> 00:00:04,200\nHello there.\n\n"
+        );
+    }
+
+    #[test]
+    fn parse_srt_round_trips_written_cues() {
+        let subs = vec![
+            subtitle(1, 0, 250, "First line."),
+            subtitle(2, 250, 6_010, "Second, with a comma."),
+        ];
+        let srt: String = subs.iter().map(subtitle_to_srt).collect();
+
+        let parsed = parse_srt(&srt).unwrap();
+        assert_eq!(parsed.len(), 2);
+        for (parsed, original) in parsed.iter().zip(&subs) {
+            assert_eq!(parsed.seq, original.seq);
+            assert_eq!(parsed.start_time_cs, original.start_time_cs);
+            assert_eq!(parsed.end_time_cs, original.end_time_cs);
+            assert_eq!(parsed.text, original.text);
+        }
+    }
+
+    #[test]
+    fn timestamp_base_accepts_optional_milliseconds() {
+        assert_eq!(parse_timestamp_base("01:02:03"), Ok(372_300));
+        assert_eq!(parse_timestamp_base("00:00:01,250"), Ok(125));
+        assert_eq!(parse_timestamp_base("00:01:30.5"), Ok(9_050));
+        assert_eq!(parse_timestamp_base("00:00:01,05"), Ok(105));
+        assert_eq!(parse_timestamp_base("00:00:01,5000"), Ok(150));
+        assert!(parse_timestamp_base("00:00:01,").is_err());
+        assert!(parse_timestamp_base("1:2").is_err());
+    }
+
+    #[test]
+    fn validate_srt_reports_each_malformed_cue() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.srt");
+        let subs = vec![subtitle(1, 0, 250, " One."), subtitle(2, 250, 400, " Two.")];
+        fs::write(&path, encode_text(&subtitles_to_srt(&subs, true), TextEncoding::Utf16le))
+            .unwrap();
+        assert_eq!(validate_srt(&path, false), Ok(()));
+
+        fs::write(
+            &path,
+            "1\n00:00:00,000 --> 00:00:02,500\nOne.\n\n\
+             3\n00:00:02,000 --> 00:00:04,000\nTwo.\n\n\
+             4\n00:00:05,000 --> 00:00:04,000\n\n\
+             5\n00:00:03,000 --> 00:00:06,000\nThree.\n\n\
+             6\n0:00:07.000 --> 00:00:08,000\nFour.\n",
+        )
+        .unwrap();
+        assert_eq!(
+            validate_srt(&path, false).unwrap_err(),
+            [
+                "line 5: cue 3 should be numbered 2",
+                "line 6: cue overlaps the previous one",
+                "line 10: cue ends before it starts",
+                "line 9: cue has no text",
+                "line 13: cue starts before the previous one",
+                "line 17: invalid timestamp line '0:00:07.000 --> 00:00:08,000'",
+            ]
+        );
+
+        // Two speakers talking at once, as --per-channel-speakers merges them
+        let said_by = |speaker: &str, sub: Subtitle| Subtitle {
+            text: format!("{}: {}", speaker, sub.text),
+            ..sub
         };
+        let subs = vec![
+            said_by("Ann", subtitle(1, 0, 300, "Hello.")),
+            said_by("Bob", subtitle(2, 100, 200, "Hi.")),
+            said_by("Ann", subtitle(3, 300, 400, "Bye.")),
+        ];
+        fs::write(&path, subtitles_to_srt(&subs, false)).unwrap();
+        assert_eq!(validate_srt(&path, true), Ok(()));
+        assert_eq!(
+            validate_srt(&path, false).unwrap_err(),
+            ["line 6: cue overlaps the previous one"]
+        );
+    }
 
-        let mut samples = vec![0.0f32; original_samples.len()];
-        match whisper_rs::convert_integer_to_float_audio(&original_samples, &mut samples) {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("Failed to convert audio samples for {}: {}", audio_path_str, e);
-                continue;
-            }
+    #[test]
+    fn parse_srt_rejects_bad_timestamps() {
+        assert!(parse_srt("1\n00:00:01 --> nonsense\ntext\n").is_err());
+    }
+
+    #[test]
+    fn empty_segments_are_dropped_and_renumbered() {
+        let mut subs = vec![
+            subtitle(1, 0, 100, " One"),
+            subtitle(2, 100, 200, "   "),
+            subtitle(3, 200, 300, ""),
+            subtitle(4, 300, 400, " Two"),
+        ];
+        drop_empty_subtitles(&mut subs);
+
+        let srt: String = subs.iter().map(subtitle_to_srt).collect();
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,000\nOne\n\n2\n00:00:03,000 --> 00:00:04,000\nTwo\n\n"
+        );
+    }
+
+    #[test]
+    fn long_subtitles_are_split_by_word_count() {
+        let subs = vec![subtitle(1, 0, 2_000, "one two three four five six")];
+        let split = split_long_subtitles(subs, 1_000);
+
+        assert_eq!(split.len(), 2);
+        assert_eq!((split[0].seq, split[0].start_time_cs, split[0].end_time_cs), (1, 0, 1_000));
+        assert_eq!(split[0].text, "one two three");
+        assert_eq!((split[1].seq, split[1].start_time_cs, split[1].end_time_cs), (2, 1_000, 2_000));
+        assert_eq!(split[1].text, "four five six");
+    }
+
+    #[test]
+    fn raw_transcript_has_no_markers_by_default() {
+        let subs = vec![subtitle(1, 0, 500, " Hello"), subtitle(2, 500, 900, " world.")];
+        assert_eq!(raw_transcript_text(&subs, None), "Hello world.");
+    }
+
+    #[test]
+    fn raw_transcript_markers_follow_interval_boundaries() {
+        let subs = vec![
+            subtitle(1, 0, 1_000, "a"),
+            subtitle(2, 1_000, 2_000, "b"),
+            subtitle(3, 2_800, 3_200, "c"),
+            subtitle(4, 3_200, 4_000, "d"),
+            subtitle(5, 6_100, 6_500, "e"),
+        ];
+        assert_eq!(
+            raw_transcript_text(&subs, Some(3_000)),
+            "[00:00:00] a b [00:00:28] c d [00:01:01] e"
+        );
+    }
+
+    #[test]
+    fn short_subtitles_merge_into_following_cue() {
+        let subs = vec![
+            subtitle(1, 0, 20, "So"),
+            subtitle(2, 20, 300, " then we left."),
+            subtitle(3, 300, 320, "Okay"),
+            subtitle(4, 320, 340, "bye"),
+        ];
+        let merged = merge_short_subtitles(subs, 50);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].seq, 1);
+        assert_eq!(merged[0].start_time_cs, 0);
+        assert_eq!(merged[0].end_time_cs, 340);
+        assert_eq!(merged[0].text, "So then we left. Okay bye");
+    }
+
+    #[test]
+    fn trailing_short_subtitle_merges_into_previous_cue() {
+        let subs = vec![
+            subtitle(1, 0, 200, "First."),
+            subtitle(2, 200, 400, "Second."),
+            subtitle(3, 400, 410, "Hm."),
+        ];
+        let merged = merge_short_subtitles(subs, 50);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[1].seq, 2);
+        assert_eq!(merged[1].start_time_cs, 200);
+        assert_eq!(merged[1].end_time_cs, 410);
+        assert_eq!(merged[1].text, "Second. Hm.");
+    }
+
+    #[test]
+    fn offset_shifts_and_clamps_at_zero() {
+        let mut subs = vec![subtitle(1, 10, 50, "a"), subtitle(2, 100, 200, "b")];
+        offset_subtitles(&mut subs, 30);
+        assert_eq!((subs[0].start_time_cs, subs[0].end_time_cs), (40, 80));
+        offset_subtitles(&mut subs, -60);
+        assert_eq!((subs[0].start_time_cs, subs[0].end_time_cs), (0, 20));
+        assert_eq!((subs[1].start_time_cs, subs[1].end_time_cs), (70, 170));
+    }
+
+    /// A mono 16kHz 16-bit WAV with a `WAVE_FORMAT_EXTENSIBLE` header and PCM
+    /// sub-format, as written by many field recorders.
+    fn extensible_wav(samples: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 8 + 40 + 8 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&40u32.to_le_bytes());
+        wav.extend_from_slice(&0xFFFEu16.to_le_bytes()); // WAVE_FORMAT_EXTENSIBLE
+        wav.extend_from_slice(&1u16.to_le_bytes()); // channels
+        wav.extend_from_slice(&16_000u32.to_le_bytes()); // sample rate
+        wav.extend_from_slice(&32_000u32.to_le_bytes()); // byte rate
+        wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+        wav.extend_from_slice(&16u16.to_le_bytes()); // container bits
+        wav.extend_from_slice(&22u16.to_le_bytes()); // extension size
+        wav.extend_from_slice(&16u16.to_le_bytes()); // valid bits
+        wav.extend_from_slice(&4u32.to_le_bytes()); // channel mask: front center
+        // KSDATAFORMAT_SUBTYPE_PCM
+        wav.extend_from_slice(&[
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38,
+            0x9B, 0x71,
+        ]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[test]
+    fn extensible_format_wavs_are_read_as_pcm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("extensible.wav");
+        fs::write(&path, extensible_wav(&[-32768, 0, 16384])).unwrap();
+        assert_eq!(parse_wav_file(&path).unwrap(), vec![-1.0, 0.0, 0.5]);
+        assert!(describe_input(&path).starts_with("WAV, 16000 Hz, 1 channel(s), 16-bit Int"));
+    }
+
+    #[test]
+    fn pcm_s16le_is_scaled_to_unit_range() {
+        let bytes = [0x00, 0x80, 0x00, 0x00, 0x00, 0x40, 0xff];
+        let samples = read_pcm_s16le(&bytes[..], None).unwrap();
+        assert_eq!(samples, vec![-1.0, 0.0, 0.5]);
+        assert!(read_pcm_s16le(&bytes[..], Some(2)).is_err());
+    }
+
+    #[test]
+    fn unknown_template_placeholders_are_reported() {
+        assert!(unknown_placeholders("{date}-{stem}.{lang}.{ext}").is_empty());
+        assert_eq!(unknown_placeholders("{stem}.{lng}.{ext}{"), vec!["{lng}"]);
+    }
+
+    #[test]
+    fn text_encodings_add_the_expected_bom() {
+        assert_eq!(encode_text("é", TextEncoding::Utf8), vec![0xC3, 0xA9]);
+        assert_eq!(
+            encode_text("é", TextEncoding::Utf8Bom),
+            vec![0xEF, 0xBB, 0xBF, 0xC3, 0xA9]
+        );
+        assert_eq!(
+            encode_text("é", TextEncoding::Utf16le),
+            vec![0xFF, 0xFE, 0xE9, 0x00]
+        );
+    }
+
+    #[test]
+    fn frames_are_rounded_at_the_given_rate() {
+        assert_eq!(cs_to_frame(0, 25.0), 0);
+        assert_eq!(cs_to_frame(150, 25.0), 38);
+        assert_eq!(cs_to_frame(1_001, 29.97), 300);
+    }
+
+    #[test]
+    fn special_tokens_are_stripped_from_text() {
+        assert_eq!(
+            strip_special_tokens(" Hello<|1.20|> world.<|endoftext|>"),
+            " Hello world."
+        );
+        assert_eq!(strip_special_tokens("a <| b"), "a <| b");
+    }
+
+    #[test]
+    fn tsv_uses_milliseconds_and_whisper_cpp_header() {
+        let subs = vec![
+            subtitle(1, 0, 250, " Hello\tthere."),
+            subtitle(2, 250, 1_234, " Bye."),
+        ];
+        assert_eq!(
+            subtitles_to_tsv(&subs),
+            "start\tend\ttext\n0\t2500\tHello there.\n2500\t12340\tBye.\n"
+        );
+    }
+
+    #[test]
+    fn format_selects_the_writers_that_run() {
+        use OutputFormat::*;
+        let formats = |extra: &[&str]| {
+            let mut argv = vec!["audio-transcriber", "in.wav"];
+            argv.extend(extra);
+            output_formats(&parse_args(&argv))
         };
+        assert_eq!(formats(&[]), [Raw, Srt, Txt]);
+        assert_eq!(formats(&["--csv"]), [Raw, Srt, Txt, Csv]);
+        assert_eq!(formats(&["--format", "tsv,srt", "--format", "srt"]), [Srt, Tsv]);
+        assert_eq!(formats(&["--no-timestamps"]), [Raw]);
+        assert_eq!(formats(&["--format", "all,tsv"]), OutputFormat::FILES);
+        assert_eq!(formats(&["--format", "all", "--format", "none", "--csv"]), []);
 
-        const SAMPLE_RATE: usize = 16000;
-        const CHUNK_SIZE: usize = 30 * SAMPLE_RATE; // 30 seconds
+        let args = parse_args(&["audio-transcriber", "in.wav", "--fps", "25"]);
+        assert!(validate(&args).iter().any(|problem| problem.contains("--fps")));
 
-        // Perform transcription
-        match handle_transcription(whisper_path, samples, CHUNK_SIZE, audio_path, args.fa) {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("Transcription failed for {}: {}", audio_path_str, e);
-                continue;
-            }
+        let subs = vec![subtitle(1, 0, 150, " Hi, there.")];
+        let mut csv = Vec::new();
+        CsvWriter { fps: Some(25.0) }.write(&subs, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "seq,start_srt,end_srt,start_cs,end_cs,start_frame,end_frame,text\n\
+             1,\"00:00:00,000\",\"00:00:01,500\",0,150,0,38,\"Hi, there.\"\n"
+        );
+        let outputs = OutputPaths::new(Path::new("in.wav"), None, &default_naming()).unwrap();
+        assert_eq!(outputs.path(Csv, &CsvWriter { fps: None }), Path::new("in_transcript.csv"));
+        assert_eq!(outputs.path(Txt, &TimestampsWriter), outputs.timestamps);
+    }
+
+    #[test]
+    fn karaoke_ass_times_each_word_from_its_tokens() {
+        let token = |text: &str, t0, t1| (text.as_bytes().to_vec(), t0, t1);
+        let words = tokens_to_words(&[
+            token("[_BEG_]", 100, 100),
+            token(" Hel", 100, 120),
+            token("lo", 120, 140),
+            token(",", 140, 145),
+            token(" {world}", 160, 200),
+            token("[_TT_50]", 200, 200),
+        ]);
+        assert_eq!(
+            words.iter().map(|word| word.text.as_str()).collect::<Vec<_>>(),
+            ["Hello,", "{world}"]
+        );
+        assert_eq!((words[0].start_time_cs, words[0].end_time_cs), (100, 145));
+
+        let mut sub = subtitle(1, 90, 210, " Hello, {world}");
+        sub.words = words;
+        assert_eq!(karaoke_text(&sub), "{\\k10}{\\k45}Hello, {\\k15}{\\k40}(world)");
+        // Once the text no longer matches the tokens, times are spread evenly
+        sub.text = "Hello there".to_string();
+        assert_eq!(karaoke_text(&sub), "{\\k60}Hello {\\k60}there");
+
+        let cue = subtitle(1, 6_012, 36_000_000, " Hi\nyou");
+        let mut ass = Vec::new();
+        AssWriter { karaoke: false }.write(&[cue], &mut ass).unwrap();
+        let ass = String::from_utf8(ass).unwrap();
+        assert!(ass.starts_with("[Script Info]\n"), "{}", ass);
+        assert!(ass.ends_with("\nDialogue: 0,0:01:00.12,100:00:00.00,Default,,0,0,0,,Hi\\Nyou\n"));
+
+        let args = parse_args(&["audio-transcriber", "in.wav", "--karaoke"]);
+        assert!(validate(&args).iter().any(|problem| problem.contains("--karaoke")));
+    }
+
+    #[test]
+    fn allocation_failures_are_told_apart_from_other_errors() {
+        use WhisperCall::*;
+        let code = WhisperError::GenericError;
+        for failure in [-6, -7, -8] {
+            assert!(is_allocation_failure(&code(failure), Full), "{}", failure);
         }
+        assert!(!is_allocation_failure(&code(-1), Full));
+        assert!(!is_allocation_failure(&code(-9), Full));
+        assert!(is_allocation_failure(&WhisperError::FailedToCreateState, CreateState));
+        assert!(!is_allocation_failure(&WhisperError::InvalidThreadCount, CreateState));
 
-        // Cleanup temp_dir
-        match temp_dir.close() {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("Failed to clean up temporary directory for {}: {}", audio_path_str, e);
-            }
+        // Detection numbers its encoder and decoder failures differently
+        assert!(is_allocation_failure(&code(-6), LangDetect));
+        assert!(is_allocation_failure(&code(-7), LangDetect));
+        assert!(!is_allocation_failure(&code(-8), LangDetect));
+        assert!(!is_allocation_failure(&code(-2), LangDetect));
+
+        // A CPU build has no GPU to fall back from
+        let args = parse_args(&["audio-transcriber", "in.wav"]);
+        let expected = compiled_backend() != "CPU";
+        assert_eq!(is_gpu_failure(&code(-6), Full, &args), expected);
+    }
+
+    #[test]
+    fn chunk_offsets_follow_the_samples_processed() {
+        let samples = vec![0.0; 70 * SAMPLE_RATE];
+        let chunks: Vec<&[f32]> = samples.chunks(30 * SAMPLE_RATE).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunk_offsets_cs(0, &chunks), [0, 3000, 6000]);
+
+        // 88-sample chunks are 0.55 cs each, which truncating and summing would lose
+        let odd: Vec<&[f32]> = samples[..SAMPLE_RATE].chunks(88).collect();
+        let offsets = chunk_offsets_cs(SAMPLE_RATE / 10, &odd);
+        assert_eq!(offsets[..4], [10, 11, 11, 12]);
+        assert_eq!(offsets.last(), Some(&110));
+    }
+
+    #[test]
+    fn heatmap_colours_segments_by_confidence() {
+        let with_confidence = |confidence, sub: Subtitle| Subtitle {
+            confidence,
+            ..sub
         };
+        let subs = vec![
+            with_confidence(Some(0.9), subtitle(1, 0, 100, " Sure thing.")),
+            with_confidence(Some(0.2), subtitle(2, 100, 400, " <Mumbles> & co")),
+            subtitle(3, 400, 500, " Read back"),
+        ];
+        let mut html = Vec::new();
+        HeatmapWriter.write(&subs, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<p>3 segments, 1 below 50% confidence</p>"), "{}", html);
+        assert!(html.contains(
+            "<rect x=\"200.00\" width=\"600.00\" height=\"60\" fill=\"hsl(24,70%,75%)\">\
+             <title>00:00:01,000 --&gt; 00:00:04,000 (20%): &lt;Mumbles&gt; &amp; co</title>"
+        ));
+        assert!(html.contains("<tr id=\"s3\" style=\"background:#ccc\"><td>00:00:04,000</td>"));
 
-        // Print outputs
-        println!(
-            "Raw output written to {}.",
-            &format!(
-                "{}_raw.txt",
-                audio_path.file_stem().unwrap().to_string_lossy()
-            )
+        // Joined cues keep the lower confidence so uncertain parts stay visible
+        let merged = merge_short_subtitles(subs[..2].to_vec(), 150);
+        assert_eq!(merged[0].confidence, Some(0.2));
+        assert_eq!(resegment_sentences(&subs[..2])[0].confidence, Some(0.9));
+        let args = parse_args(&["audio-transcriber", "in.wav", "--confidence-heatmap"]);
+        assert_eq!(output_formats(&args).last(), Some(&OutputFormat::Heatmap));
+    }
+
+    #[test]
+    fn cues_keep_their_chunk_language_and_can_be_tagged() {
+        let in_lang = |lang: &str, sub: Subtitle| Subtitle {
+            lang: Some(lang.to_string()),
+            ..sub
+        };
+        let subs = vec![
+            in_lang("en", subtitle(1, 0, 100, " Good morning. Bue")),
+            in_lang("es", subtitle(2, 100, 200, " nos días. Hola.")),
+        ];
+        let args = parse_args(&[
+            "audio-transcriber",
+            "in.wav",
+            "--tag-language",
+            "--resegment-sentences",
+        ]);
+        let tagged = postprocess_subtitles(subs.clone(), &args);
+        let texts: Vec<&str> = tagged.iter().map(|sub| sub.text.as_str()).collect();
+        assert_eq!(texts, ["[en] Good morning.", "[en] Bue nos días.", "[es] Hola."]);
+        assert_eq!(segment_json(&subs[1])["lang"], "es");
+
+        let plain = postprocess_subtitles(subs, &parse_args(&["audio-transcriber", "in.wav"]));
+        assert_eq!(plain[0].text, " Good morning. Bue");
+    }
+
+    #[test]
+    fn inputs_without_a_usable_stem_are_named_transcript() {
+        let base = |path: &str| output_base(Path::new(path));
+        assert_eq!(base(".wav"), "transcript");
+        assert_eq!(base("/tmp/"), "transcript");
+        assert_eq!(base(".."), "transcript");
+        assert_eq!(base(""), "transcript");
+        assert_eq!(base("audio"), "audio");
+        assert_eq!(base("dir/talk.v2.mp3"), "talk.v2");
+        assert_eq!(base(".hidden.wav"), ".hidden");
+
+        let outputs = OutputPaths::new(Path::new(".wav"), None, &default_naming()).unwrap();
+        assert_eq!(outputs.srt, PathBuf::from("transcript_timestamps.srt"));
+    }
+
+    #[test]
+    fn appended_cues_continue_the_master_numbering_and_timeline() {
+        let dir = tempfile::tempdir().unwrap();
+        let master = dir.path().join("journal.srt");
+        let morning = [subtitle(1, 0, 250, " Good morning."), subtitle(2, 300, 500, " Coffee.")];
+        assert_eq!(append_to_master(&master, &morning, false, TextEncoding::Utf8).unwrap(), 2);
+        // A hand-edited master may lose its trailing blank line
+        let content = fs::read_to_string(&master).unwrap();
+        fs::write(&master, content.trim_end()).unwrap();
+
+        let evening = [subtitle(1, 100, 200, " Good night.")];
+        append_to_master(&master, &evening, false, TextEncoding::Utf8).unwrap();
+        let cues = parse_srt(&fs::read_to_string(&master).unwrap()).unwrap();
+        let cues: Vec<_> =
+            cues.iter().map(|sub| (sub.seq, sub.start_time_cs, sub.end_time_cs)).collect();
+        assert_eq!(cues, [(1, 0, 250), (2, 300, 500), (3, 600, 700)]);
+
+        // A UTF-16 master is read back and rewritten with a single byte order mark
+        let master = dir.path().join("journal16.srt");
+        append_to_master(&master, &morning, true, TextEncoding::Utf16le).unwrap();
+        append_to_master(&master, &evening, true, TextEncoding::Utf16le).unwrap();
+        let bytes = fs::read(&master).unwrap();
+        assert!(bytes.starts_with(&[0xFF, 0xFE]));
+        let content = decode_text(bytes).unwrap();
+        assert!(!content.contains('\u{feff}'));
+        assert_eq!(parse_srt(&content).unwrap().len(), 3);
+        assert!(validate_srt(&master, false).is_ok());
+    }
+
+    #[test]
+    fn model_aliases_resolve_to_known_models() {
+        assert_eq!(resolve_model("turbo"), PathBuf::from("ggml-large-v3-turbo.bin"));
+        assert_eq!(canonical_model_name("tiny-en"), Some("tiny.en"));
+        assert_eq!(canonical_model_name("Large-V3-Turbo-Q5_0"), Some("large-v3-turbo-q5_0"));
+        let args = parse_args(&["audio-transcriber", "in.wav", "--model", "turbo"]);
+        assert_eq!(args.model.model_path.as_deref(), Some("turbo"));
+        assert_eq!(resolve_model("tiny.en"), PathBuf::from("ggml-tiny.en.bin"));
+        assert_eq!(resolve_model("huge"), PathBuf::from("huge"));
+        for (alias, target) in MODEL_ALIASES {
+            assert!(known_models().iter().any(|model| model.name == *target), "{}", alias);
+            assert!(known_models().iter().all(|model| model.name != *alias), "{}", alias);
+        }
+    }
+
+    #[test]
+    fn replacements_apply_in_order_with_capture_groups() {
+        let args = parse_args(&[
+            "audio-transcriber",
+            "in.wav",
+            "--replace",
+            r"(?i)\bdarn\b=>d***",
+            "--replace",
+            r"(\d+) percent=>$1%",
+            "--replace",
+            r"\s*\[MUSIC\]=>",
+        ]);
+        let mut text = " Darn, 50 percent [MUSIC] of darning.".to_string();
+        apply_replacements(&mut text, &args.text.replace);
+        assert_eq!(text, " d***, 50% of darning.");
+        assert!(parse_replacement("no arrow").is_err());
+        assert!(parse_replacement("(=>x").is_err());
+    }
+
+    #[test]
+    fn subcommands_and_flat_invocation_fill_in_shared_options() {
+        Cli::command().debug_assert();
+
+        let flat = parse_args(&["audio-transcriber", "--csv", "a.wav", "b.wav"]);
+        assert_eq!(flat.audio_paths, ["a.wav", "b.wav"]);
+        assert!(flat.out.csv);
+
+        let detect = parse_args(&["audio-transcriber", "detect-lang", "a.wav"]);
+        assert!(detect.detect_language);
+        assert_eq!(detect.audio_paths, ["a.wav"]);
+
+        let reformat = parse_args(&["audio-transcriber", "reformat", "a.srt", "--csv"]);
+        assert_eq!(reformat.reformat, Some(PathBuf::from("a.srt")));
+        assert!(reformat.out.csv && reformat.audio_paths.is_empty());
+        let reformat = parse_args(&["audio-transcriber", "reformat", "a.srt", "--replace", "a=>b"]);
+        assert_eq!(reformat.text.replace.len(), 1);
+
+        let transcribe = parse_args(&["audio-transcriber", "transcribe", "a.wav"]);
+        assert_eq!(transcribe.audio_paths, ["a.wav"]);
+
+        let serve = parse_args(&["audio-transcriber", "serve", "--port", "9000", "--prompt", "Hi"]);
+        assert_eq!(serve.serve, Some(SocketAddr::from(([127, 0, 0, 1], 9000))));
+        assert_eq!(serve.whisper.prompt.as_deref(), Some("Hi"));
+        assert!(serve.audio_paths.is_empty());
+        // IPv6 hosts need brackets around them once the port is added
+        let serve = parse_args(&["audio-transcriber", "serve", "--host", "::1", "--port", "9000"]);
+        assert_eq!(serve.serve.map(|addr| addr.to_string()).as_deref(), Some("[::1]:9000"));
+
+        // Each subcommand only takes the options it uses
+        for argv in [
+            &["audio-transcriber", "serve", "--csv"][..],
+            &["audio-transcriber", "reformat", "a.srt", "--model-path", "base.en"],
+            &["audio-transcriber", "detect-lang", "a.wav", "--strip-brackets"],
+        ] {
+            assert!(Cli::try_parse_from(argv).is_err(), "{:?}", argv);
+        }
+
+        assert!(matches!(
+            Cli::parse_from(["audio-transcriber", "list-models"]).into_action(),
+            Action::ListModels
+        ));
+        assert!(Cli::try_parse_from(["audio-transcriber", "list-models", "--csv"]).is_err());
+    }
+
+    #[test]
+    fn bundle_moves_outputs_into_one_folder_per_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut summaries = Vec::new();
+        for (input, file) in [("a/talk.mp3", "talk.srt"), ("b/talk.wav", "talk (1).srt")] {
+            let output = dir.path().join(file);
+            fs::write(&output, input).unwrap();
+            summaries.push(FileSummary {
+                input: input.to_string(),
+                manifest_line: None,
+                status: "ok",
+                outputs: vec![output.display().to_string()],
+                duration_secs: None,
+                language: None,
+                error: None,
+            });
+        }
+
+        let bundle = dir.path().join("out.zip");
+        write_bundle(&bundle, &mut summaries).unwrap();
+        assert!(!dir.path().join("talk.srt").exists());
+        assert_eq!(
+            summaries[1].outputs,
+            [format!("{}:talk (2)/talk (1).srt", bundle.display())]
         );
-        println!(
-            "Timestamped output written to {} and {}.",
-            &format!(
-                "{}_timestamps.txt",
-                audio_path.file_stem().unwrap().to_string_lossy()
-            ),
-            &format!(
-                "{}_timestamps.srt",
-                audio_path.file_stem().unwrap().to_string_lossy()
-            )
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&bundle).unwrap()).unwrap();
+        let mut content = String::new();
+        let mut entry = archive.by_name("talk/talk.srt").unwrap();
+        entry.read_to_string(&mut content).unwrap();
+        drop(entry);
+        assert_eq!(content, "a/talk.mp3");
+        assert!(archive.by_name("talk (2)/talk (1).srt").is_ok());
+    }
+
+    #[test]
+    fn transcript_tail_starts_at_a_word_boundary() {
+        let subs = vec![
+            subtitle(1, 0, 100, " Welcome to part one."),
+            subtitle(2, 100, 200, " "),
+            subtitle(3, 200, 300, " Next, Dr. Müller speaks."),
+        ];
+        assert_eq!(
+            transcript_tail(&subs, 200),
+            "Welcome to part one. Next, Dr. Müller speaks."
+        );
+        assert_eq!(transcript_tail(&subs, 20), "Dr. Müller speaks.");
+        assert_eq!(transcript_tail(&[], 20), "");
+    }
+
+    #[test]
+    fn huge_inputs_need_allow_large() {
+        let limit = MAX_DECODED_BYTES / 4;
+        assert!(check_decoded_size(limit, "a.wav", false).is_ok());
+        let err = check_decoded_size(limit + 1, "a.wav", false).unwrap_err();
+        assert!(err.contains("--allow-large") && err.contains("2.0 GiB"), "{}", err);
+        assert!(check_decoded_size(limit + 1, "a.wav", true).is_ok());
+    }
+
+    #[test]
+    fn incremental_srt_needs_srt_output() {
+        let args = parse_args(&["audio-transcriber", "in.wav", "--incremental-srt"]);
+        assert!(validate(&args).iter().all(|problem| !problem.contains("--incremental-srt")));
+        let argv = ["audio-transcriber", "in.wav", "--incremental-srt", "--format", "csv"];
+        assert!(validate(&parse_args(&argv)).iter().any(|problem| problem.contains("--incremental")));
+    }
+
+    #[test]
+    fn lang_candidates_pick_the_likeliest_allowed_language() {
+        let (en, fr, cy) = (0, 2, 5);
+        let mut probs = vec![0.0; 10];
+        (probs[en], probs[fr], probs[cy]) = (0.3, 0.1, 0.5);
+        assert_eq!(most_likely_candidate(&probs, &[en as c_int, fr as c_int]), Some(en as c_int));
+        probs[fr] = 0.3;
+        assert_eq!(most_likely_candidate(&probs, &[fr as c_int, en as c_int]), Some(fr as c_int));
+        assert_eq!(most_likely_candidate(&probs, &[]), None);
+
+        let args = parse_args(&["audio-transcriber", "in.wav", "--lang-candidates", "en,fr"]);
+        assert!(validate(&args).contains(&"--lang-candidates needs --language auto".to_string()));
+        let argv = ["audio-transcriber", "in.wav", "--language", "auto", "--lang-candidates", "fr"];
+        assert!(validate(&parse_args(&argv)).iter().all(|p| !p.contains("--lang-candidates")));
+    }
+
+    #[test]
+    fn streamed_segments_are_shifted_like_the_written_files() {
+        let args = parse_args(&[
+            "audio-transcriber",
+            "in.wav",
+            "--offset",
+            "-2000",
+            "--timestamp-base",
+            "01:00:00",
+        ]);
+        let mut subs = vec![subtitle(1, 100, 300, " Hi.")];
+        shift_to_output_time(&mut subs, &args);
+        // The negative offset clamps at zero before the base is added, as in postprocess_subtitles
+        assert_eq!((subs[0].start_time_cs, subs[0].end_time_cs), (360_000, 360_100));
+    }
+
+    #[test]
+    fn max_duration_refuses_longer_inputs() {
+        assert!(check_max_duration(3600.0, "a.wav", None).is_ok());
+        assert!(check_max_duration(60.0, "a.wav", Some(60.0)).is_ok());
+        let err = check_max_duration(60.5, "a.wav", Some(60.0)).unwrap_err();
+        assert_eq!(err, "a.wav is 60.5s long, more than the 60s allowed by --max-duration");
+
+        // Without ffprobe, decoding stops a second past the limit
+        assert_eq!(max_duration_decode_secs(Some(60.0)), Some(61.0));
+        assert!(check_decoded_duration(60 * SAMPLE_RATE, "a.wav", Some(60.0)).is_ok());
+        let err = check_decoded_duration(61 * SAMPLE_RATE, "a.wav", Some(60.0)).unwrap_err();
+        assert_eq!(err, "a.wav is longer than the 60s allowed by --max-duration");
+
+        let args = parse_args(&["audio-transcriber", "in.wav", "--max-duration", "0"]);
+        assert!(validate(&args).iter().any(|problem| problem.contains("--max-duration")));
+    }
+
+    #[test]
+    fn no_timestamps_only_plans_the_raw_transcript() {
+        let args = parse_args(&["audio-transcriber", "in.wav", "--no-timestamps"]);
+        assert_eq!(output_formats(&args), vec![OutputFormat::Raw]);
+        assert!(validate(&args).iter().all(|problem| !problem.contains("--no-timestamps")));
+
+        let argv = ["audio-transcriber", "in.wav", "--no-timestamps", "--output", "out.srt"];
+        let args = parse_args(&argv);
+        assert!(output_formats(&args).is_empty());
+        assert!(validate(&args).iter().any(|problem| problem.contains("--no-timestamps")));
+        let argv = ["audio-transcriber", "in.wav", "--no-timestamps", "--output", "out.txt"];
+        assert_eq!(output_formats(&parse_args(&argv)), vec![OutputFormat::Raw]);
+
+        let argv = ["audio-transcriber", "in.wav", "--no-timestamps", "--confidence-heatmap"];
+        assert!(Cli::try_parse_from(argv).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn writability_is_checked_where_outputs_go() {
+        // Outputs land in the working directory, not next to the input
+        let args = parse_args(&["audio-transcriber", "/proc/version", "--dry-run"]);
+        assert!(validate(&args).iter().all(|problem| !problem.contains("not writable")));
+        let argv = ["audio-transcriber", "in.wav", "--output", "/proc/out.srt", "--dry-run"];
+        let problems = validate(&parse_args(&argv));
+        assert!(problems.contains(&"Output directory /proc is not writable".to_string()));
+    }
+
+    #[test]
+    fn languages_with_a_template_get_a_default_prompt() {
+        let mut args = parse_args(&["audio-transcriber", "in.wav", "--language", "ja"]);
+        assert_eq!(initial_prompt(&args), default_prompt("ja").unwrap());
+        args.whisper.prompt = Some("会議の記録。".to_string());
+        assert_eq!(initial_prompt(&args), "会議の記録。");
+
+        let args =
+            parse_args(&["audio-transcriber", "in.wav", "--language", "ja", "--no-default-prompt"]);
+        assert_eq!(initial_prompt(&args), "experience");
+        for lang in ["en", "auto"] {
+            let args = parse_args(&["audio-transcriber", "in.wav", "--language", lang]);
+            assert_eq!(initial_prompt(&args), "experience");
+        }
+        for (lang, _) in DEFAULT_PROMPTS {
+            assert!(whisper_rs::get_lang_id(lang).is_some(), "{}", lang);
+        }
+    }
+
+    #[test]
+    fn vocab_terms_join_the_prompt_and_fix_near_misses() {
+        let terms = parse_vocab("# people\nMüller\n\n  Kubernetes  \nhabeas corpus\n");
+        assert_eq!(terms, ["Müller", "Kubernetes", "habeas corpus"]);
+
+        let mut args = parse_args(&["audio-transcriber", "in.wav"]);
+        assert_eq!(initial_prompt(&args), "experience");
+        args.whisper.prompt = Some("Court hearing.".to_string());
+        args.text.vocab_terms = terms.clone();
+        args.previous_transcript = Some("The witness left.".to_string());
+        assert_eq!(
+            initial_prompt(&args),
+            "Court hearing. Müller, Kubernetes, habeas corpus The witness left."
+        );
+
+        assert_eq!(
+            correct_vocab("mr. müller runs kubernetis, not Kubernetes.", &terms),
+            "mr. Müller runs Kubernetes, not Kubernetes."
+        );
+        // Short words are only respelled when they match ignoring case
+        assert_eq!(correct_vocab("Schmitt", &["Schmidt".to_string()]), "Schmidt");
+        assert_eq!(correct_vocab("Kant", &["Kent".to_string()]), "Kant");
+    }
+
+    #[test]
+    fn utterances_are_sliced_and_numbered_after_existing_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("utt_0004.wav"), b"").unwrap();
+        let samples = vec![0.5; 3 * SAMPLE_RATE];
+        let subs = vec![
+            subtitle(1, 0, 150, " First."),
+            subtitle(2, 150, 150, " Empty span."),
+            subtitle(3, 200, 400, " Runs past the end. "),
+        ];
+
+        assert_eq!(export_utterances(&subs, &samples, dir.path()).unwrap(), 2);
+        let reader = WavReader::open(dir.path().join("utt_0005.wav")).unwrap();
+        assert_eq!(reader.duration() as usize, SAMPLE_RATE * 3 / 2);
+        let reader = WavReader::open(dir.path().join("utt_0006.wav")).unwrap();
+        assert_eq!(reader.duration() as usize, SAMPLE_RATE);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("utt_0006.txt")).unwrap(),
+            "Runs past the end.\n"
+        );
+    }
+
+    #[test]
+    fn ffmpeg_binary_is_extracted_from_zip_and_tar_xz() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut zipped = io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut zipped);
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("readme.txt", options).unwrap();
+        writer.start_file("ffmpeg", options).unwrap();
+        io::Write::write_all(&mut writer, b"zipped ffmpeg").unwrap();
+        writer.finish().unwrap();
+        let dest = dir.path().join("from-zip");
+        extract_ffmpeg_from_zip(zipped.get_ref(), &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"zipped ffmpeg");
+
+        let mut tarball = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(10);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tarball
+            .append_data(&mut header, "ffmpeg-7.0-amd64-static/ffmpeg", &b"tar ffmpeg"[..])
+            .unwrap();
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut tarball.into_inner().unwrap().as_slice(), &mut xz).unwrap();
+        let dest = dir.path().join("from-tar");
+        extract_ffmpeg_from_tar_xz(&xz, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"tar ffmpeg");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&dest).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn streamed_segments_use_milliseconds() {
+        let line = segment_json(&subtitle(3, 150, 275, " Hello \"there\". ")).to_string();
+        assert_eq!(line, r#"{"end_ms":2750,"seq":3,"start_ms":1500,"text":"Hello \"there\"."}"#);
+    }
+
+    #[test]
+    fn text_preview_takes_the_first_words_across_segments() {
+        let subs = vec![subtitle(1, 0, 100, " One two"), subtitle(2, 100, 200, "  three four ")];
+        assert_eq!(text_preview(&subs, 3), "One two three ...");
+        assert_eq!(text_preview(&subs, 4), "One two three four");
+        assert_eq!(text_preview(&[], 4), "");
+    }
+
+    #[test]
+    fn srt_uses_crlf_only_when_asked() {
+        let subs = vec![subtitle(1, 0, 150, " Hi.")];
+        assert_eq!(subtitles_to_srt(&subs, false), "1\n00:00:00,000 --> 00:00:01,500\nHi.\n\n");
+        assert_eq!(
+            subtitles_to_srt(&subs, true),
+            "1\r\n00:00:00,000 --> 00:00:01,500\r\nHi.\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn whisper_progress_moves_the_bar_within_a_chunk() {
+        let pb = indicatif::ProgressBar::hidden();
+        pb.set_length(2 * BAR_STEPS_PER_CHUNK);
+        let mut progress = ChunkProgress::Bar(pb.clone());
+        let mut callback = progress.whisper_callback().unwrap();
+        callback(50);
+        assert_eq!(pb.position(), 50);
+        callback(100);
+        assert_eq!(pb.position(), 99);
+        progress.inc();
+        assert_eq!(pb.position(), 100);
+        callback(30);
+        assert_eq!(pb.position(), 130);
+    }
+
+    #[test]
+    fn rtl_marks_follow_the_language() {
+        assert!(uses_rtl_marks(RtlMode::Auto, "ar"));
+        assert!(!uses_rtl_marks(RtlMode::Auto, "en"));
+        assert!(uses_rtl_marks(RtlMode::Always, "en"));
+        assert!(!uses_rtl_marks(RtlMode::Never, "he"));
+
+        let marked = mark_rtl(&[subtitle(1, 0, 100, " 3 كتب.\nمرحبا ")]);
+        assert_eq!(marked[0].text, "\u{200F}3 كتب.\n\u{200F}مرحبا");
+    }
+
+    #[test]
+    fn channel_isolation_runs_before_the_other_filters() {
+        let mut args = parse_args(&["audio-transcriber", "in.wav"]);
+        assert_eq!(input_filter_chain(&args, None), None);
+        assert_eq!(input_filter_chain(&args, Some(1)).as_deref(), Some("pan=mono|c0=c1"));
+        args.input.denoise = true;
+        assert_eq!(
+            input_filter_chain(&args, Some(0)).as_deref(),
+            Some("pan=mono|c0=c0,afftdn")
+        );
+    }
+
+    #[test]
+    fn reduced_audio_ctx_shortens_chunks() {
+        let with_ctx = |ctx: &str| parse_args(&["audio-transcriber", "in.wav", "--audio-ctx", ctx]);
+        assert_eq!(chunk_size(&parse_args(&["audio-transcriber", "in.wav"])), CHUNK_SIZE);
+        assert_eq!(chunk_size(&with_ctx("0")), CHUNK_SIZE);
+        assert_eq!(chunk_size(&with_ctx("1500")), CHUNK_SIZE);
+        // 750 positions of 20ms each cover 15 seconds
+        assert_eq!(chunk_size(&with_ctx("750")), 15 * SAMPLE_RATE);
+    }
+
+    #[test]
+    fn speech_bounds_skip_leading_and_trailing_silence() {
+        let second = SAMPLE_RATE;
+        let mut samples = vec![0.0; 5 * second];
+        samples[2 * second..3 * second].fill(0.5);
+        samples[4 * second + 100] = 0.001; // far below the threshold
+        let speech = speech_bounds(&samples, SILENCE_THRESHOLD_DB, SILENCE_PADDING);
+        assert_eq!(speech, 2 * second - SILENCE_PADDING..3 * second + SILENCE_PADDING);
+        assert_eq!(speech.start % (SAMPLE_RATE / 100), 0);
+
+        assert_eq!(speech_bounds(&[0.0; 1000], SILENCE_THRESHOLD_DB, SILENCE_PADDING), 0..0);
+        // Padding stops at the edges of the audio
+        assert_eq!(speech_bounds(&[0.5; 1000], SILENCE_THRESHOLD_DB, SILENCE_PADDING), 0..1000);
+    }
+
+    #[test]
+    fn clean_speech_filter_chain_can_be_overridden() {
+        let mut args = parse_args(&["audio-transcriber", "in.wav"]);
+        assert_eq!(audio_filter_chain(&args), None);
+        args.input.clean_speech = true;
+        assert_eq!(
+            audio_filter_chain(&args).as_deref(),
+            Some("highpass=f=80,lowpass=f=8000")
+        );
+        args.input.highpass = Some(200);
+        args.input.denoise = true;
+        assert_eq!(
+            audio_filter_chain(&args).as_deref(),
+            Some("highpass=f=200,lowpass=f=8000,afftdn")
+        );
+    }
+
+    #[test]
+    fn split_output_partitions_by_start_time() {
+        let subs = vec![
+            subtitle(1, 0, 500, "a"),
+            subtitle(2, 5_900, 6_100, "b"),
+            subtitle(3, 6_100, 6_500, "c"),
+            subtitle(4, 18_200, 18_400, "d"),
+        ];
+        let parts = split_subtitles_by_time(&subs, 6_000, true);
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0].len(), 2);
+        assert_eq!(parts[1][0].seq, 1);
+        assert_eq!((parts[1][0].start_time_cs, parts[1][0].end_time_cs), (100, 500));
+        assert!(parts[2].is_empty());
+        assert_eq!(parts[3][0].start_time_cs, 200);
+
+        let absolute = split_subtitles_by_time(&subs, 6_000, false);
+        assert_eq!(absolute[3][0].start_time_cs, 18_200);
+    }
+
+    #[test]
+    fn srt_parts_are_numbered_before_the_extension() {
+        let outputs = OutputPaths::new(Path::new("book.mp3"), None, &default_naming()).unwrap();
+        assert_eq!(outputs.srt_part(7), PathBuf::from("book_timestamps_part007.srt"));
+    }
+
+    #[test]
+    fn manifest_skips_comments_and_keeps_line_numbers() {
+        let manifest = "# interviews\n\nday1/a.wav\n  https://example.com/b.mp3?x=1  \n# done\n";
+        assert_eq!(
+            parse_manifest(manifest),
+            vec![
+                (3, "day1/a.wav".to_string()),
+                (4, "https://example.com/b.mp3?x=1".to_string()),
+            ]
+        );
+        assert_eq!(url_file_name("https://example.com/b.mp3?x=1"), "b.mp3");
+        assert_eq!(url_file_name("https://example.com/"), "download");
+    }
+
+    #[test]
+    fn sentences_are_resegmented_across_cues() {
+        let subs = vec![
+            subtitle(1, 0, 400, " We went to St. louis. It was"),
+            subtitle(2, 400, 600, " great! Really?"),
+        ];
+        let sentences = resegment_sentences(&subs);
+        let texts: Vec<&str> = sentences.iter().map(|sub| sub.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["We went to St. louis.", "It was great!", "Really?"]
+        );
+        assert_eq!(sentences[0].start_time_cs, 0);
+        assert_eq!(sentences[0].end_time_cs, 5 * 400 / 7);
+        assert_eq!(sentences[1].end_time_cs, 500);
+        assert_eq!(sentences[2].seq, 3);
+        assert_eq!(sentences[2].end_time_cs, 600);
+        assert!(sentences.iter().all(|sub| sub.words.is_empty()));
+
+        // Token times are kept, and a long pause ends an unpunctuated sentence
+        let word = |start_time_cs, end_time_cs, text: &str| Word {
+            start_time_cs,
+            end_time_cs,
+            text: text.to_string(),
+        };
+        let mut first = subtitle(1, 0, 300, " So then I");
+        first.words = vec![word(0, 50, "So"), word(50, 250, "then"), word(250, 300, "I")];
+        let mut second = subtitle(2, 300, 400, " left. Done");
+        second.words = vec![word(300, 340, "left."), word(340, 400, "Done")];
+        let later = subtitle(3, 700, 800, " and then more.");
+        let sentences = resegment_sentences(&[first, second, later]);
+        let texts: Vec<&str> = sentences.iter().map(|sub| sub.text.as_str()).collect();
+        assert_eq!(texts, vec!["So then I left.", "Done", "and then more."]);
+        assert_eq!(sentences[0].words.len(), 4);
+        assert_eq!(sentences[0].words[1], word(50, 250, "then"));
+        assert_eq!(sentences[1].words, vec![word(340, 400, "Done")]);
+        assert!(sentences[2].words.is_empty());
+    }
+
+    #[test]
+    fn output_paths_keep_spaces_and_parentheses() {
+        let outputs =
+            OutputPaths::new(Path::new("my recording (2).wav"), None, &default_naming()).unwrap();
+        assert_eq!(outputs.srt, PathBuf::from("my recording (2)_timestamps.srt"));
+        assert_eq!(outputs.timestamps, PathBuf::from("my recording (2)_timestamps.txt"));
+        assert_eq!(outputs.raw, PathBuf::from("my recording (2)_raw.txt"));
+    }
+
+    #[test]
+    fn output_template_fills_placeholders() {
+        let naming = OutputNaming {
+            template: Some("{stem}.{lang}.{ext}".to_string()),
+            ..default_naming()
+        };
+        let outputs = OutputPaths::new(Path::new("talk.mp3"), None, &naming).unwrap();
+        assert_eq!(outputs.srt, PathBuf::from("talk.en.srt"));
+        assert_eq!(outputs.raw, PathBuf::from("talk.en.raw.txt"));
+    }
+
+    #[test]
+    fn no_overwrite_numbers_only_when_a_written_file_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let naming = OutputNaming {
+            template: Some(format!("{}/{{stem}}.{{ext}}", dir.path().display())),
+            no_overwrite: true,
+            writers: vec![(OutputFormat::Csv, Box::new(CsvWriter { fps: None }))],
+            ..default_naming()
+        };
+        let csv_path =
+            |outputs: &OutputPaths| outputs.path(OutputFormat::Csv, &CsvWriter { fps: None });
+        // Only the CSV is written, so a leftover raw transcript doesn't matter
+        fs::write(dir.path().join("talk.raw.txt"), "").unwrap();
+        let outputs = OutputPaths::new(Path::new("talk.mp3"), None, &naming).unwrap();
+        assert_eq!(csv_path(&outputs), dir.path().join("talk.csv"));
+
+        fs::write(dir.path().join("talk.csv"), "").unwrap();
+        let outputs = OutputPaths::new(Path::new("talk.mp3"), None, &naming).unwrap();
+        assert_eq!(csv_path(&outputs), dir.path().join("talk (1).csv"));
+
+        let naming = OutputNaming {
+            writers: vec![(
+                OutputFormat::Srt,
+                Box::new(SrtWriter {
+                    crlf: false,
+                    color_speakers: Vec::new(),
+                }),
+            )],
+            split_output: true,
+            ..naming
+        };
+        fs::write(dir.path().join("talk_part001.srt"), "").unwrap();
+        let outputs = OutputPaths::new(Path::new("talk.mp3"), None, &naming).unwrap();
+        assert_eq!(outputs.srt_part(1), dir.path().join("talk (1)_part001.srt"));
+    }
+
+    #[test]
+    fn lang_suffix_goes_before_the_extension() {
+        let naming = OutputNaming {
+            lang: "es".to_string(),
+            lang_suffix: true,
+            ..default_naming()
+        };
+        let outputs = OutputPaths::new(Path::new("talk.mp3"), None, &naming).unwrap();
+        assert_eq!(outputs.srt, PathBuf::from("talk_timestamps.es.srt"));
+        assert_eq!(outputs.raw, PathBuf::from("talk_raw.es.txt"));
+    }
+
+    #[test]
+    fn strip_fillers_keeps_ordinary_words_and_sentence_punctuation() {
+        let fillers = parse_vocab(DEFAULT_FILLERS);
+        let strip = |text| strip_fillers(text, &fillers);
+        assert_eq!(
+            strip(" Um, I think the the plan is, you know, fine."),
+            "I think the plan is, fine."
+        );
+        assert_eq!(strip(" So, like, I like it, um."), "So, I like it.");
+        assert_eq!(strip(" I was like, wow. Uh, yes."), "I was like, wow. Yes.");
+        assert_eq!(strip(" No, no, do you know him?"), "No, no, do you know him?");
+        assert_eq!(strip_fillers("basically it works", &["basically".to_string()]), "it works");
+
+        let subs = vec![subtitle(1, 0, 100, " Um."), subtitle(2, 100, 200, " Uh, right.")];
+        let stripped = strip_fillers_from_subtitles(&subs, &fillers);
+        assert_eq!(stripped.len(), 1);
+        assert_eq!((stripped[0].seq, stripped[0].text.as_str()), (1, "Right."));
+    }
+
+    #[test]
+    fn speakers_keep_their_srt_colour() {
+        let said_by = |speaker: &str, sub: Subtitle| Subtitle {
+            speaker: Some(speaker.to_string()),
+            ..sub
+        };
+        let subs = vec![
+            said_by("Bob", subtitle(1, 0, 100, " Bob: Hi.")),
+            said_by("Ann", subtitle(2, 100, 200, " Ann: Hello.")),
+            subtitle(3, 200, 300, " [Music]"),
+            said_by("Bob", subtitle(4, 300, 400, " Bob: Bye.")),
+        ];
+        let mut srt = Vec::new();
+        // Colours follow the channel order, not who speaks first
+        let writer = SrtWriter {
+            crlf: false,
+            color_speakers: vec!["Ann".to_string(), "Bob".to_string()],
+        };
+        writer.write(&subs, &mut srt).unwrap();
+        let srt = String::from_utf8(srt).unwrap();
+        assert!(srt.contains("\n<font color=\"#00ffff\">Bob: Hi.</font>\n"), "{}", srt);
+        assert!(srt.contains("\n<font color=\"#ffff00\">Ann: Hello.</font>\n"), "{}", srt);
+        assert!(srt.contains("\n[Music]\n"), "{}", srt);
+        assert!(srt.contains("\n<font color=\"#00ffff\">Bob: Bye.</font>\n"), "{}", srt);
+    }
+
+    #[test]
+    fn output_extension_selects_the_only_format_written() {
+        use OutputFormat::*;
+        let args = parse_args(&["audio-transcriber", "in.wav", "--output", "out/result.VTT"]);
+        assert_eq!(output_formats(&args), [Vtt]);
+        let argv = ["audio-transcriber", "in.wav", "--format", "srt", "--output", "a.txt"];
+        assert_eq!(output_formats(&parse_args(&argv)), [Raw, Srt]);
+        assert!(parse_output_path("result.docx").is_err());
+        assert!(parse_output_path("result").is_err());
+
+        let naming = OutputNaming {
+            output: Some(PathBuf::from("out/result.srt")),
+            ..default_naming()
+        };
+        let outputs = OutputPaths::new(Path::new("in.wav"), None, &naming).unwrap();
+        assert_eq!(outputs.srt, PathBuf::from("out/result.srt"));
+        let srt_writer = SrtWriter {
+            crlf: false,
+            color_speakers: Vec::new(),
+        };
+        assert_eq!(outputs.path(Srt, &srt_writer), Path::new("out/result.srt"));
+        assert_eq!(outputs.path(Vtt, &VttWriter), Path::new("in_transcript.vtt"));
+
+        let subs = vec![subtitle(1, 6_150, 6_300, " Hello.")];
+        let mut vtt = Vec::new();
+        VttWriter.write(&subs, &mut vtt).unwrap();
+        assert_eq!(
+            String::from_utf8(vtt).unwrap(),
+            "WEBVTT\n\n00:01:01.500 --> 00:01:03.000\nHello.\n"
+        );
+        let multiline = vec![subtitle(1, 0, 100, " A\n\n\n \nB --> C")];
+        let mut vtt = Vec::new();
+        VttWriter.write(&multiline, &mut vtt).unwrap();
+        assert_eq!(
+            String::from_utf8(vtt).unwrap(),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nA\nB --&gt; C\n"
         );
+        let mut lrc = Vec::new();
+        LrcWriter.write(&subs, &mut lrc).unwrap();
+        assert_eq!(String::from_utf8(lrc).unwrap(), "[01:01.50]Hello.\n");
     }
 }
@@ -0,0 +1,220 @@
+//! Live transcription from the default input device.
+//!
+//! Captured audio is downmixed to mono, resampled to 16kHz and decoded in a
+//! rolling window on one persistent whisper state. Every segment except the
+//! newest is printed once a decode finishes, since only the newest one can
+//! still change as more audio arrives.
+
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SizedSample};
+use whisper_rs::WhisperContext;
+
+use crate::{
+    Args, CHUNK_SIZE, INTERRUPTED, MIN_SAMPLES, SAMPLE_RATE, Subtitle, base_params,
+    collect_segments, cs_to_srt_time,
+};
+
+/// How much new audio to gather before decoding the window again.
+const STEP_SAMPLES: usize = 5 * SAMPLE_RATE;
+/// Samples per centisecond at 16kHz.
+const SAMPLES_PER_CS: usize = SAMPLE_RATE / 100;
+
+pub(crate) fn transcribe_microphone(
+    ctx: &WhisperContext,
+    args: &Args,
+) -> Result<(), Box<dyn Error>> {
+    let host = cpal::default_host();
+    let device = host
+        .default_input_device()
+        .ok_or("No microphone or other input device available")?;
+    let supported = device.default_input_config()?;
+    let channels = supported.channels() as usize;
+    let device_rate = supported.sample_rate().0;
+    let config = supported.config();
+
+    let (tx, rx) = mpsc::channel();
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, channels, tx)?,
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, channels, tx)?,
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, channels, tx)?,
+        format => return Err(format!("Unsupported input sample format {:?}", format).into()),
+    };
+    stream.play()?;
+    eprintln!(
+        "Listening on {} ({} Hz, {} channel(s)); press Ctrl-C to stop",
+        device.name().unwrap_or_else(|_| "the default input".to_string()),
+        device_rate,
+        channels
+    );
+
+    let mut state = ctx.create_state()?;
+    let params = base_params(args);
+    let mut resampler = Resampler::new(device_rate, SAMPLE_RATE as u32);
+    // Audio not yet covered by a printed segment, and where it starts in the session
+    let mut window: Vec<f32> = Vec::new();
+    let mut window_start_cs: i64 = 0;
+    let mut new_samples = 0;
+    let mut seq = 1;
+
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(buffer) => {
+                let before = window.len();
+                resampler.process(&buffer, &mut window);
+                new_samples += window.len() - before;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if new_samples < STEP_SAMPLES {
+            continue;
+        }
+        new_samples = 0;
+
+        state.full(params.clone(), &window)?;
+        let segments = collect_segments(&state, window_start_cs, seq, false)?;
+        // A full window can't grow any further, so everything in it is final
+        let window_full = window.len() >= CHUNK_SIZE;
+        let final_count = if window_full {
+            segments.len()
+        } else {
+            segments.len().saturating_sub(1)
+        };
+        if final_count == 0 && !window_full {
+            continue;
+        }
+
+        print_segments(&segments[..final_count]);
+        seq += final_count as u32;
+        let cut_cs = match segments.get(final_count) {
+            Some(next) => next.start_time_cs as i64 - window_start_cs,
+            None => (window.len() / SAMPLES_PER_CS) as i64,
+        };
+        let cut = (cut_cs.max(0) as usize * SAMPLES_PER_CS).min(window.len());
+        window.drain(..cut);
+        window_start_cs += (cut / SAMPLES_PER_CS) as i64;
+    }
+
+    drop(stream);
+    // Whatever is left after stopping is final as well
+    if window.len() >= MIN_SAMPLES {
+        state.full(params.clone(), &window)?;
+        print_segments(&collect_segments(&state, window_start_cs, seq, false)?);
+    }
+    Ok(())
+}
+
+fn print_segments(segments: &[Subtitle]) {
+    for sub in segments {
+        let text = sub.text.trim();
+        if !text.is_empty() {
+            println!(
+                "[{} --> {}] {}",
+                cs_to_srt_time(sub.start_time_cs),
+                cs_to_srt_time(sub.end_time_cs),
+                text
+            );
+        }
+    }
+}
+
+/// Open an input stream that downmixes each frame to mono `f32` and sends the
+/// buffers to `tx`.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    channels: usize,
+    tx: mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let mono = data
+                .chunks(channels)
+                .map(|frame| {
+                    frame.iter().map(|&s| f32::from_sample(s)).sum::<f32>() / frame.len() as f32
+                })
+                .collect();
+            // The receiver only goes away once transcription has stopped
+            let _ = tx.send(mono);
+        },
+        |err| eprintln!("Microphone stream error: {}", err),
+        None,
+    )
+}
+
+/// Streaming linear-interpolation resampler that keeps its position between
+/// buffers, so timestamps don't drift over a long session.
+struct Resampler {
+    step: f64,
+    pos: f64,
+    prev: f32,
+}
+
+impl Resampler {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler {
+            step: from_rate as f64 / to_rate as f64,
+            pos: 0.0,
+            prev: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        let Some(&last) = input.last() else {
+            return;
+        };
+        // `pos` is relative to `input`, where -1 refers to the previous buffer's last sample
+        let end = input.len() as f64 - 1.0;
+        while self.pos < end {
+            let index = self.pos.floor();
+            let frac = (self.pos - index) as f32;
+            let a = if index < 0.0 {
+                self.prev
+            } else {
+                input[index as usize]
+            };
+            let b = input[(index + 1.0) as usize];
+            out.push(a + (b - a) * frac);
+            self.pos += self.step;
+        }
+        self.pos -= input.len() as f64;
+        self.prev = last;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resampler_halves_rate_across_buffers() {
+        let mut resampler = Resampler::new(32000, 16000);
+        let mut out = Vec::new();
+        resampler.process(&[0.0, 0.1, 0.2], &mut out);
+        resampler.process(&[0.3, 0.4, 0.5, 0.6], &mut out);
+        let expected = [0.0, 0.2, 0.4];
+        assert_eq!(out.len(), expected.len());
+        for (got, want) in out.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+        }
+    }
+
+    #[test]
+    fn resampler_interpolates_between_buffers() {
+        let mut resampler = Resampler::new(8000, 16000);
+        let mut out = Vec::new();
+        resampler.process(&[0.0, 1.0], &mut out);
+        resampler.process(&[0.0], &mut out);
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 0.5]);
+    }
+}
@@ -0,0 +1,193 @@
+//! `probe`: report an input's duration, codec, channels, sample rate and bit
+//! rate without converting or transcribing it.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use hound::{SampleFormat, WavReader};
+use serde::Serialize;
+
+use crate::ffprobe_path;
+
+/// What `probe` found out about one input. Fields ffprobe doesn't report for
+/// a container are left out.
+#[derive(Debug, Default, Serialize)]
+struct ProbeReport {
+    input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channels: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<u32>,
+    /// Bits per second of the audio stream, or of the whole file if the stream
+    /// doesn't say
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bit_rate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Probe each input and print its report, as text or, with `json`, one JSON
+/// line per input. Returns whether every input could be probed.
+pub(crate) fn probe_inputs(paths: &[String], json: bool) -> bool {
+    let mut all_ok = true;
+    for path in paths {
+        let report = probe(Path::new(path)).unwrap_or_else(|e| {
+            all_ok = false;
+            ProbeReport {
+                error: Some(e),
+                ..ProbeReport::default()
+            }
+        });
+        let report = ProbeReport {
+            input: path.clone(),
+            ..report
+        };
+        if json {
+            println!("{}", serde_json::to_string(&report).unwrap_or_default());
+        } else {
+            print_report(&report);
+        }
+    }
+    all_ok
+}
+
+fn print_report(report: &ProbeReport) {
+    if let Some(error) = &report.error {
+        eprintln!("{}: {}", report.input, error);
+        return;
+    }
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+    println!("{}", report.input);
+    println!(
+        "  Duration:    {}",
+        or_unknown(report.duration_secs.map(|secs| format!("{:.2}s", secs)))
+    );
+    println!("  Codec:       {}", or_unknown(report.codec.clone()));
+    println!("  Channels:    {}", or_unknown(report.channels.map(|n| n.to_string())));
+    println!(
+        "  Sample rate: {}",
+        or_unknown(report.sample_rate.map(|hz| format!("{} Hz", hz)))
+    );
+    println!(
+        "  Bit rate:    {}",
+        or_unknown(report.bit_rate.map(|bps| format!("{} kb/s", bps / 1000)))
+    );
+}
+
+/// Read the details from the header of a WAV file hound understands, and ask
+/// ffprobe about anything else.
+fn probe(path: &Path) -> Result<ProbeReport, String> {
+    if !path.exists() {
+        return Err("does not exist".to_string());
+    }
+    match WavReader::open(path) {
+        Ok(reader) => Ok(wav_report(&reader)),
+        Err(_) => probe_with_ffprobe(path),
+    }
+}
+
+fn wav_report<R: std::io::Read>(reader: &WavReader<R>) -> ProbeReport {
+    let spec = reader.spec();
+    let kind = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, _) => 'f',
+        (SampleFormat::Int, 8) => 'u',
+        (SampleFormat::Int, _) => 's',
+    };
+    ProbeReport {
+        duration_secs: Some(reader.duration() as f64 / spec.sample_rate as f64),
+        codec: Some(format!("pcm_{}{}le", kind, spec.bits_per_sample)),
+        channels: Some(spec.channels.into()),
+        sample_rate: Some(spec.sample_rate),
+        bit_rate: Some(
+            spec.sample_rate as u64 * spec.channels as u64 * spec.bits_per_sample as u64,
+        ),
+        ..ProbeReport::default()
+    }
+}
+
+fn probe_with_ffprobe(path: &Path) -> Result<ProbeReport, String> {
+    let output = Command::new(ffprobe_path())
+        .args(["-v", "error", "-select_streams", "a:0", "-of", "json", "-show_entries"])
+        .arg("format=duration,bit_rate:stream=codec_name,channels,sample_rate,bit_rate")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run ffprobe (is it installed?): {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe could not read it: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_ffprobe_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The report for ffprobe's `-of json` output. ffprobe writes most numbers
+/// as strings, and omits those a container doesn't store.
+fn parse_ffprobe_json(json: &str) -> Result<ProbeReport, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("unexpected ffprobe output: {}", e))?;
+    let stream = value["streams"]
+        .get(0)
+        .ok_or("no audio stream; the file may be corrupt or contain only video")?;
+    let format = &value["format"];
+    let number = |field: &serde_json::Value| match field {
+        serde_json::Value::String(text) => text.parse::<f64>().ok(),
+        other => other.as_f64(),
+    };
+    Ok(ProbeReport {
+        duration_secs: number(&format["duration"]),
+        codec: stream["codec_name"].as_str().map(str::to_string),
+        channels: number(&stream["channels"]).map(|n| n as u32),
+        sample_rate: number(&stream["sample_rate"]).map(|hz| hz as u32),
+        bit_rate: number(&stream["bit_rate"])
+            .or(number(&format["bit_rate"]))
+            .map(|bps| bps as u64),
+        ..ProbeReport::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ffprobe_output_and_wav_headers_give_the_same_fields() {
+        let json = r#"{
+            "programs": [],
+            "streams": [
+                { "codec_name": "aac", "sample_rate": "48000", "channels": 2 }
+            ],
+            "format": { "duration": "12.480000", "bit_rate": "131072" }
+        }"#;
+        let report = parse_ffprobe_json(json).unwrap();
+        assert_eq!(report.duration_secs, Some(12.48));
+        assert_eq!(report.codec.as_deref(), Some("aac"));
+        assert_eq!((report.channels, report.sample_rate), (Some(2), Some(48_000)));
+        assert_eq!(report.bit_rate, Some(131_072));
+        let err = parse_ffprobe_json(r#"{"streams": [], "format": {}}"#).unwrap_err();
+        assert!(err.contains("no audio stream"), "{}", err);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..8_000 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let report = probe(&path).unwrap();
+        assert_eq!(report.duration_secs, Some(0.5));
+        assert_eq!(report.codec.as_deref(), Some("pcm_s16le"));
+        assert_eq!(report.bit_rate, Some(256_000));
+    }
+}
@@ -0,0 +1,198 @@
+//! Local HTTP transcription service for `serve`.
+//!
+//! The model is loaded once and every request is decoded on it in turn, so
+//! requests are handled one at a time. Audio is POSTed to `/transcribe`, either
+//! as the raw request body or as the first file of a multipart/form-data
+//! upload, and the transcript comes back as JSON.
+
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    Args, INTERRUPTED, MAX_DECODED_BYTES, Model, check_decoded_duration, check_max_duration,
+    chunk_size, decode_with_ffmpeg, handle_transcription, input_filter_chain,
+    max_duration_decode_secs, probe_duration, raw_transcript_text, segment_json,
+};
+
+/// Largest upload accepted, whatever its format.
+const MAX_UPLOAD_BYTES: usize = 1 << 30;
+
+/// A failed request: the HTTP status and a message for the `error` field.
+type Failure = (u16, String);
+
+pub(crate) fn serve(
+    addr: SocketAddr,
+    model: &Model,
+    whisper_path: &Path,
+    args: &Args,
+) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(addr).map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+    println!("Listening on http://{}/transcribe (Ctrl-C to stop)", addr);
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        // Wake up regularly so Ctrl-C stops the server between requests
+        let Some(mut request) = server.recv_timeout(Duration::from_millis(500))? else {
+            continue;
+        };
+        let (status, body) = match transcribe_request(&mut request, model, whisper_path, args) {
+            Ok(body) => (200, body),
+            Err((status, message)) => {
+                eprintln!("{} {}: {}", request.method(), request.url(), message);
+                (status, serde_json::json!({ "error": message }))
+            }
+        };
+        let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Run one request through the pipeline and build its JSON transcript.
+fn transcribe_request(
+    request: &mut Request,
+    model: &Model,
+    whisper_path: &Path,
+    args: &Args,
+) -> Result<serde_json::Value, Failure> {
+    let path = request.url().split('?').next().unwrap_or_default();
+    if path != "/transcribe" {
+        return Err((404, format!("Unknown path {}; POST audio to /transcribe", path)));
+    }
+    if *request.method() != Method::Post {
+        return Err((405, "Use POST to upload audio".to_string()));
+    }
+    if request.body_length().is_some_and(|len| len > MAX_UPLOAD_BYTES) {
+        return Err((413, "Upload is larger than 1 GiB".to_string()));
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| (400, format!("Failed to read the upload: {}", e)))?;
+    if body.len() > MAX_UPLOAD_BYTES {
+        return Err((413, "Upload is larger than 1 GiB".to_string()));
+    }
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str())
+        .unwrap_or_default();
+    let audio = if content_type.starts_with("multipart/form-data") {
+        multipart_file(content_type, &body)
+            .ok_or((400, "No file found in the multipart upload".to_string()))?
+    } else {
+        &body
+    };
+    if audio.is_empty() {
+        return Err((400, "The upload is empty".to_string()));
+    }
+
+    // ffmpeg needs a file to probe, so the upload is staged in a temp directory
+    let failed = |e: &dyn std::fmt::Display| (500, e.to_string());
+    let dir = tempfile::tempdir().map_err(|e| failed(&e))?;
+    let upload = dir.path().join("upload");
+    fs::write(&upload, audio).map_err(|e| failed(&e))?;
+    if args.input.max_duration.is_some()
+        && let Some(secs) = probe_duration(&upload)
+    {
+        check_max_duration(secs, "The upload", args.input.max_duration).map_err(|e| (413, e))?;
+    }
+    let filter = input_filter_chain(args, None);
+    let max_samples =
+        (!args.input.allow_large).then_some(MAX_DECODED_BYTES as usize / size_of::<f32>());
+    // Without ffprobe only the start of a long upload is decoded before it is refused
+    let max_secs = max_duration_decode_secs(args.input.max_duration);
+    let samples = decode_with_ffmpeg(&upload, filter.as_deref(), max_samples, max_secs)
+        .map_err(|e| (422, format!("Failed to decode the audio: {}", e)))?;
+    if samples.is_empty() {
+        return Err((422, "The upload contains no audio".to_string()));
+    }
+    check_decoded_duration(samples.len(), "The upload", args.input.max_duration)
+        .map_err(|e| (413, e))?;
+
+    let (subtitles, report) =
+        handle_transcription(model, whisper_path, &samples, chunk_size(args), args, None)
+            .map_err(|e| failed(&e))?;
+    Ok(serde_json::json!({
+        "language": report.language.unwrap_or(&args.whisper.language),
+        "duration_secs": report.duration_secs,
+        "text": raw_transcript_text(&subtitles, None),
+        "segments": subtitles.iter().map(segment_json).collect::<Vec<_>>(),
+    }))
+}
+
+/// The contents of the first file in a multipart/form-data `body`, or of the
+/// first part at all when none is marked as a file.
+fn multipart_file<'a>(content_type: &str, body: &'a [u8]) -> Option<&'a [u8]> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{}", boundary).into_bytes();
+    // A delimiter only counts at the start of a line
+    let starts: Vec<usize> = find_all(body, &delimiter)
+        .filter(|&i| i == 0 || body[..i].ends_with(b"\r\n"))
+        .collect();
+
+    let mut first = None;
+    for pair in starts.windows(2) {
+        // Each part runs from the line after its delimiter to the CRLF before the next one
+        let part = body[pair[0] + delimiter.len()..pair[1]]
+            .strip_prefix(b"\r\n")?
+            .strip_suffix(b"\r\n")?;
+        let header_end = find_all(part, b"\r\n\r\n").next()?;
+        let headers = String::from_utf8_lossy(&part[..header_end]).to_ascii_lowercase();
+        let content = &part[header_end + 4..];
+        if headers.contains("filename=") {
+            return Some(content);
+        }
+        first.get_or_insert(content);
+    }
+    first
+}
+
+/// Offsets at which `needle` occurs in `haystack`.
+fn find_all<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == needle)
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_uploads_yield_the_file_part() {
+        let body = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"language\"\r\n\r\n\
+            en\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\
+            Content-Type: audio/wav\r\n\r\n\
+            RIFF\r\n--Xy\r\n\
+            --XyZ--\r\n";
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        assert_eq!(
+            multipart_file(content_type, body),
+            Some(b"RIFF\r\n--Xy".as_slice())
+        );
+        assert_eq!(multipart_file("multipart/form-data", body), None);
+    }
+}
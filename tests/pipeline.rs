@@ -0,0 +1,104 @@
+//! End-to-end tests running the binary with the `tiny` model, on a committed
+//! tone fixture and on whisper.cpp's public-domain JFK sample. The model and
+//! the sample are downloaded once into the target directory, so the tests only
+//! run when `AUDIO_TRANSCRIBER_MODEL_TESTS` is set. They also need ffmpeg on
+//! the PATH.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const MODEL_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny.bin";
+// 2s of 16kHz mono: half a second of silence, a second of 440Hz tone, then silence
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tone.wav");
+// 11s of "And so, my fellow Americans, ask not what your country can do for you..."
+const SPEECH_URL: &str = "https://github.com/ggml-org/whisper.cpp/raw/master/samples/jfk.wav";
+const EXIT_NO_SPEECH: i32 = 3;
+
+/// Download `url` to `cache_dir/file_name` unless it is already there.
+fn cached_download(url: &str, cache_dir: &Path, file_name: &str) -> PathBuf {
+    let path = cache_dir.join(file_name);
+    if !path.exists() {
+        fs::create_dir_all(cache_dir).unwrap();
+        let response = reqwest::blocking::get(url).unwrap();
+        assert!(response.status().is_success(), "downloading {} failed", url);
+        // Write to a temporary name first so an interrupted download isn't cached
+        let partial = path.with_extension("part");
+        fs::write(&partial, response.bytes().unwrap()).unwrap();
+        fs::rename(&partial, &path).unwrap();
+    }
+    path
+}
+
+/// Directory the model and sample are cached in, or `None` when model tests
+/// are disabled.
+fn model_test_cache() -> Option<PathBuf> {
+    if std::env::var_os("AUDIO_TRANSCRIBER_MODEL_TESTS").is_none() {
+        eprintln!("skipping: set AUDIO_TRANSCRIBER_MODEL_TESTS=1 to download the tiny model");
+        return None;
+    }
+    Some(Path::new(env!("CARGO_TARGET_TMPDIR")).join("model-tests"))
+}
+
+#[test]
+fn transcribes_fixture_with_tiny_model() {
+    let Some(cache_dir) = model_test_cache() else {
+        return;
+    };
+    let model = cached_download(MODEL_URL, &cache_dir, "ggml-tiny.bin");
+
+    let work_dir = tempfile::tempdir().unwrap();
+    fs::copy(FIXTURE, work_dir.path().join("tone.wav")).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_audio-transcriber"))
+        .current_dir(work_dir.path())
+        .arg("tone.wav")
+        .arg("--model-path")
+        .arg(&model)
+        .status()
+        .unwrap();
+    // The clip has no words, so whisper may find no speech at all
+    assert!(
+        status.success() || status.code() == Some(EXIT_NO_SPEECH),
+        "unexpected exit: {}",
+        status
+    );
+
+    // Every output is written even when it ends up empty
+    fs::read_to_string(work_dir.path().join("tone_raw.txt")).unwrap();
+    let srt = fs::read_to_string(work_dir.path().join("tone_timestamps.srt")).unwrap();
+    assert!(
+        srt.is_empty() || srt.starts_with("1\n00:00:0"),
+        "unexpected SRT: {}",
+        srt
+    );
+    fs::read_to_string(work_dir.path().join("tone_timestamps.txt")).unwrap();
+}
+
+#[test]
+fn transcribes_speech_with_tiny_model() {
+    let Some(cache_dir) = model_test_cache() else {
+        return;
+    };
+    let model = cached_download(MODEL_URL, &cache_dir, "ggml-tiny.bin");
+    let speech = cached_download(SPEECH_URL, &cache_dir, "jfk.wav");
+
+    let work_dir = tempfile::tempdir().unwrap();
+    fs::copy(&speech, work_dir.path().join("jfk.wav")).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_audio-transcriber"))
+        .current_dir(work_dir.path())
+        .arg("jfk.wav")
+        .arg("--model-path")
+        .arg(&model)
+        .arg("--language")
+        .arg("en")
+        .status()
+        .unwrap();
+    assert!(status.success(), "unexpected exit: {}", status);
+
+    let raw = fs::read_to_string(work_dir.path().join("jfk_raw.txt")).unwrap();
+    assert!(raw.to_lowercase().contains("country"), "unexpected transcript: {}", raw);
+    let srt = fs::read_to_string(work_dir.path().join("jfk_timestamps.srt")).unwrap();
+    assert!(srt.starts_with("1\n00:00:0"), "unexpected SRT: {}", srt);
+}